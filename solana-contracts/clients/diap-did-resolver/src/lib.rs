@@ -0,0 +1,292 @@
+//! DIAP DID Resolver
+//!
+//! Resolves `did:diap:<agent-pubkey>` identifiers to W3C DID Documents built
+//! from the `DidDocument` account kept by the diap-verification program.
+//! Fetching the account is left to the caller so any RPC client can be used.
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::AccountDeserialize;
+use diap_verification::{
+    DidDocument, KeyType, VerificationMethod, RELATIONSHIP_ASSERTION_METHOD,
+    RELATIONSHIP_AUTHENTICATION, RELATIONSHIP_CAPABILITY_DELEGATION,
+    RELATIONSHIP_CAPABILITY_INVOCATION, RELATIONSHIP_KEY_AGREEMENT,
+};
+use serde_json::{json, Value};
+
+pub const DID_METHOD_PREFIX: &str = "did:diap:";
+
+/// Multicodec prefixes used in `publicKeyMultibase`
+const ED25519_PUB_MULTICODEC: [u8; 2] = [0xed, 0x01];
+const X25519_PUB_MULTICODEC: [u8; 2] = [0xec, 0x01];
+
+const RELATIONSHIPS: [(u8, &str); 5] = [
+    (RELATIONSHIP_AUTHENTICATION, "authentication"),
+    (RELATIONSHIP_ASSERTION_METHOD, "assertionMethod"),
+    (RELATIONSHIP_KEY_AGREEMENT, "keyAgreement"),
+    (RELATIONSHIP_CAPABILITY_INVOCATION, "capabilityInvocation"),
+    (RELATIONSHIP_CAPABILITY_DELEGATION, "capabilityDelegation"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolveError {
+    /// Not a `did:diap:<base58 pubkey>` identifier
+    InvalidDid,
+    /// Account data is not a `DidDocument`
+    InvalidAccountData,
+    /// Account belongs to a different agent than the DID names
+    SubjectMismatch,
+}
+
+impl std::fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolveError::InvalidDid => write!(f, "invalid did:diap identifier"),
+            ResolveError::InvalidAccountData => write!(f, "invalid DID document account data"),
+            ResolveError::SubjectMismatch => write!(f, "DID document subject does not match DID"),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+pub fn did_for(agent: &Pubkey) -> String {
+    format!("{DID_METHOD_PREFIX}{agent}")
+}
+
+pub fn parse_did(did: &str) -> Result<Pubkey, ResolveError> {
+    did.strip_prefix(DID_METHOD_PREFIX)
+        .and_then(|key| key.parse().ok())
+        .ok_or(ResolveError::InvalidDid)
+}
+
+/// Address of the account that backs `did:diap:<agent>`
+pub fn document_address(agent: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"did", agent.as_ref()], &diap_verification::ID).0
+}
+
+pub fn decode_document(mut data: &[u8]) -> Result<DidDocument, ResolveError> {
+    DidDocument::try_deserialize(&mut data).map_err(|_| ResolveError::InvalidAccountData)
+}
+
+/// Resolve `did` from the raw data of the account at `document_address`
+pub fn resolve(did: &str, account_data: &[u8]) -> Result<Value, ResolveError> {
+    let agent = parse_did(did)?;
+    let document = decode_document(account_data)?;
+    if document.agent != agent {
+        return Err(ResolveError::SubjectMismatch);
+    }
+    Ok(to_did_json(&document))
+}
+
+/// Render a `DidDocument` account as DID Document JSON
+pub fn to_did_json(document: &DidDocument) -> Value {
+    let did = did_for(&document.agent);
+
+    let controllers: Vec<String> = document.controllers.iter().map(did_for).collect();
+    let controller = match controllers.as_slice() {
+        [single] => json!(single),
+        _ => json!(controllers),
+    };
+
+    let verification_methods: Vec<Value> = document
+        .verification_methods
+        .iter()
+        .map(|method| verification_method_json(&did, method))
+        .collect();
+
+    let services: Vec<Value> = document
+        .services
+        .iter()
+        .map(|service| {
+            json!({
+                "id": format!("{did}#{}", service.id),
+                "type": service.service_type,
+                "serviceEndpoint": service.endpoint,
+            })
+        })
+        .collect();
+
+    let mut json = json!({
+        "@context": [
+            "https://www.w3.org/ns/did/v1",
+            "https://w3id.org/security/suites/ed25519-2020/v1",
+            "https://w3id.org/security/suites/x25519-2020/v1",
+        ],
+        "id": did,
+        "controller": controller,
+        "verificationMethod": verification_methods,
+        "service": services,
+    });
+
+    for (flag, name) in RELATIONSHIPS {
+        let ids: Vec<String> = document
+            .verification_methods
+            .iter()
+            .filter(|method| method.relationships & flag != 0)
+            .map(|method| format!("{did}#{}", method.id))
+            .collect();
+        if !ids.is_empty() {
+            json[name] = json!(ids);
+        }
+    }
+
+    json
+}
+
+fn verification_method_json(did: &str, method: &VerificationMethod) -> Value {
+    let (method_type, multicodec) = if method.key_type == KeyType::X25519 as u8 {
+        ("X25519KeyAgreementKey2020", X25519_PUB_MULTICODEC)
+    } else {
+        ("Ed25519VerificationKey2020", ED25519_PUB_MULTICODEC)
+    };
+
+    let mut key = multicodec.to_vec();
+    key.extend_from_slice(&method.public_key);
+
+    json!({
+        "id": format!("{did}#{}", method.id),
+        "type": method_type,
+        "controller": did,
+        "publicKeyMultibase": format!("z{}", bs58::encode(key).into_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::AccountSerialize;
+    use diap_verification::ServiceEndpoint;
+
+    fn sample_document(agent: Pubkey) -> DidDocument {
+        DidDocument {
+            agent,
+            controllers: vec![agent],
+            verification_methods: vec![
+                VerificationMethod {
+                    id: "key-1".to_string(),
+                    key_type: KeyType::Ed25519 as u8,
+                    public_key: agent.to_bytes(),
+                    relationships: RELATIONSHIP_AUTHENTICATION
+                        | RELATIONSHIP_ASSERTION_METHOD
+                        | RELATIONSHIP_CAPABILITY_INVOCATION,
+                },
+                VerificationMethod {
+                    id: "key-2".to_string(),
+                    key_type: KeyType::X25519 as u8,
+                    public_key: [7u8; 32],
+                    relationships: RELATIONSHIP_KEY_AGREEMENT,
+                },
+            ],
+            services: vec![ServiceEndpoint {
+                id: "messaging".to_string(),
+                service_type: "DIAPMessaging".to_string(),
+                endpoint: "https://agent.example/inbox".to_string(),
+            }],
+            created_at: 1,
+            updated_at: 2,
+            bump: 255,
+        }
+    }
+
+    fn account_data(document: &DidDocument) -> Vec<u8> {
+        let mut data = Vec::new();
+        document.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    fn multibase(multicodec: [u8; 2], key: &[u8; 32]) -> String {
+        let mut bytes = multicodec.to_vec();
+        bytes.extend_from_slice(key);
+        format!("z{}", bs58::encode(bytes).into_string())
+    }
+
+    #[test]
+    fn parse_did_round_trips_and_rejects_other_identifiers() {
+        let agent = Pubkey::new_unique();
+        assert_eq!(parse_did(&did_for(&agent)), Ok(agent));
+
+        assert_eq!(parse_did(&format!("did:web:{agent}")), Err(ResolveError::InvalidDid));
+        assert_eq!(parse_did("did:diap:not-a-key"), Err(ResolveError::InvalidDid));
+        assert_eq!(parse_did("did:diap:"), Err(ResolveError::InvalidDid));
+        assert_eq!(parse_did(""), Err(ResolveError::InvalidDid));
+    }
+
+    #[test]
+    fn resolve_checks_subject_and_discriminator() {
+        let agent = Pubkey::new_unique();
+        let document = sample_document(agent);
+        let data = account_data(&document);
+
+        assert_eq!(resolve(&did_for(&agent), &data), Ok(to_did_json(&document)));
+        assert_eq!(
+            resolve(&did_for(&Pubkey::new_unique()), &data),
+            Err(ResolveError::SubjectMismatch)
+        );
+
+        let mut bad_discriminator = data.clone();
+        bad_discriminator[0] ^= 1;
+        assert_eq!(
+            resolve(&did_for(&agent), &bad_discriminator),
+            Err(ResolveError::InvalidAccountData)
+        );
+        assert_eq!(
+            resolve(&did_for(&agent), &data[..data.len() / 2]),
+            Err(ResolveError::InvalidAccountData)
+        );
+        assert_eq!(resolve("did:diap:bad", &data), Err(ResolveError::InvalidDid));
+    }
+
+    #[test]
+    fn to_did_json_renders_keys_and_relationships() {
+        let agent = Pubkey::new_unique();
+        let did = did_for(&agent);
+        let json = to_did_json(&sample_document(agent));
+
+        assert_eq!(json["id"], did);
+        assert_eq!(json["controller"], did);
+
+        let methods = json["verificationMethod"].as_array().unwrap();
+        assert_eq!(methods.len(), 2);
+        assert_eq!(methods[0]["id"], format!("{did}#key-1"));
+        assert_eq!(methods[0]["type"], "Ed25519VerificationKey2020");
+        assert_eq!(methods[0]["controller"], did);
+        assert_eq!(
+            methods[0]["publicKeyMultibase"],
+            multibase(ED25519_PUB_MULTICODEC, &agent.to_bytes())
+        );
+        assert_eq!(methods[1]["type"], "X25519KeyAgreementKey2020");
+        assert_eq!(
+            methods[1]["publicKeyMultibase"],
+            multibase(X25519_PUB_MULTICODEC, &[7u8; 32])
+        );
+
+        let key1 = json!([format!("{did}#key-1")]);
+        assert_eq!(json["authentication"], key1);
+        assert_eq!(json["assertionMethod"], key1);
+        assert_eq!(json["capabilityInvocation"], key1);
+        assert_eq!(json["keyAgreement"], json!([format!("{did}#key-2")]));
+        assert!(json.get("capabilityDelegation").is_none());
+
+        assert_eq!(
+            json["service"],
+            json!([{
+                "id": format!("{did}#messaging"),
+                "type": "DIAPMessaging",
+                "serviceEndpoint": "https://agent.example/inbox",
+            }])
+        );
+    }
+
+    #[test]
+    fn to_did_json_lists_multiple_controllers() {
+        let agent = Pubkey::new_unique();
+        let delegate = Pubkey::new_unique();
+        let mut document = sample_document(agent);
+        document.controllers.push(delegate);
+
+        assert_eq!(
+            to_did_json(&document)["controller"],
+            json!([did_for(&agent), did_for(&delegate)])
+        );
+    }
+}
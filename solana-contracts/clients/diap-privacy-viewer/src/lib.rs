@@ -0,0 +1,352 @@
+//! DIAP Privacy Viewer
+//!
+//! Off-chain helper for compliance view keys. Senders encrypt note memos to a
+//! registered x25519 view key; the key holder scans program logs for
+//! `FundsLockedEvent` and `PrivacyPaymentExecutedEvent` and decrypts the memos
+//! addressed to them.
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use diap_payment_privacy::{FundsLockedEvent, PrivacyPaymentExecutedEvent};
+use rand_core::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const EPHEMERAL_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const MEMO_KDF_DOMAIN: &[u8] = b"diap-note-memo";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoError {
+    /// Memo is shorter than the ephemeral key and nonce header
+    Malformed,
+    /// Memo was not encrypted to this view key or was tampered with
+    DecryptionFailed,
+    EncryptionFailed,
+}
+
+impl std::fmt::Display for MemoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoError::Malformed => write!(f, "malformed memo"),
+            MemoError::DecryptionFailed => write!(f, "memo decryption failed"),
+            MemoError::EncryptionFailed => write!(f, "memo encryption failed"),
+        }
+    }
+}
+
+impl std::error::Error for MemoError {}
+
+/// Viewing key pair. The public half is registered on-chain with `register_view_key`.
+pub struct ViewKey {
+    secret: StaticSecret,
+}
+
+impl ViewKey {
+    pub fn from_secret(secret: [u8; 32]) -> Self {
+        Self {
+            secret: StaticSecret::from(secret),
+        }
+    }
+
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let mut secret = [0u8; 32];
+        rng.fill_bytes(&mut secret);
+        Self::from_secret(secret)
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        PublicKey::from(&self.secret).to_bytes()
+    }
+
+    pub fn decrypt_memo(&self, memo: &[u8]) -> Result<Vec<u8>, MemoError> {
+        if memo.len() < EPHEMERAL_KEY_LEN + NONCE_LEN {
+            return Err(MemoError::Malformed);
+        }
+        let (ephemeral, rest) = memo.split_at(EPHEMERAL_KEY_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let mut ephemeral_key = [0u8; 32];
+        ephemeral_key.copy_from_slice(ephemeral);
+        let ephemeral_key = PublicKey::from(ephemeral_key);
+
+        let shared = self.secret.diffie_hellman(&ephemeral_key);
+        let key = memo_key(shared.as_bytes(), ephemeral_key.as_bytes(), &self.public_key());
+
+        ChaCha20Poly1305::new(Key::from_slice(&key))
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| MemoError::DecryptionFailed)
+    }
+}
+
+/// Encrypt `plaintext` to `view_key`, producing the memo layout the program expects:
+/// ephemeral x25519 key (32) || nonce (12) || ChaCha20-Poly1305 ciphertext
+pub fn encrypt_memo<R: RngCore + CryptoRng>(
+    rng: &mut R,
+    view_key: &[u8; 32],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, MemoError> {
+    let ephemeral = ViewKey::generate(rng);
+    let ephemeral_key = ephemeral.public_key();
+    let shared = ephemeral.secret.diffie_hellman(&PublicKey::from(*view_key));
+    let key = memo_key(shared.as_bytes(), &ephemeral_key, view_key);
+
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill_bytes(&mut nonce);
+
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| MemoError::EncryptionFailed)?;
+
+    let mut memo = Vec::with_capacity(EPHEMERAL_KEY_LEN + NONCE_LEN + ciphertext.len());
+    memo.extend_from_slice(&ephemeral_key);
+    memo.extend_from_slice(&nonce);
+    memo.extend_from_slice(&ciphertext);
+    Ok(memo)
+}
+
+fn memo_key(shared: &[u8; 32], ephemeral_key: &[u8; 32], view_key: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(MEMO_KDF_DOMAIN);
+    hasher.update(shared);
+    hasher.update(ephemeral_key);
+    hasher.update(view_key);
+    hasher.finalize().into()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoteKind {
+    Deposit {
+        commitment: [u8; 32],
+        leaf_index: u64,
+        locker: Pubkey,
+    },
+    Payment {
+        nullifier: [u8; 32],
+        to: Pubkey,
+        change_commitment: [u8; 32],
+        change_leaf_index: u64,
+        relayer_fee: u64,
+    },
+}
+
+/// Privacy event whose memo decrypted under the scanning view key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecryptedNote {
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub kind: NoteKind,
+    pub memo: Vec<u8>,
+}
+
+/// Scan the log messages of one transaction and decrypt every memo addressed to
+/// `view_key`. Only events logged while the privacy program is the running
+/// program are considered, so another program cannot inject look-alike events.
+pub fn scan_logs(view_key: &ViewKey, logs: &[String]) -> Vec<DecryptedNote> {
+    let program_id = diap_payment_privacy::ID.to_string();
+    let mut invoke_stack: Vec<&str> = Vec::new();
+    let mut notes = Vec::new();
+
+    for log in logs {
+        if let Some(rest) = log.strip_prefix("Program ") {
+            let mut parts = rest.split_whitespace();
+            let (id, action) = (parts.next(), parts.next());
+            match (id, action) {
+                (Some(id), Some("invoke")) => invoke_stack.push(id),
+                (Some(id), Some("success" | "failed:")) if invoke_stack.last() == Some(&id) => {
+                    invoke_stack.pop();
+                }
+                _ => {}
+            }
+        }
+
+        if invoke_stack.last() != Some(&program_id.as_str()) {
+            continue;
+        }
+        let Some(data) = log.strip_prefix("Program data: ") else {
+            continue;
+        };
+        let Ok(bytes) = STANDARD.decode(data) else {
+            continue;
+        };
+        if let Some(note) = decode_event(view_key, &bytes) {
+            notes.push(note);
+        }
+    }
+
+    notes
+}
+
+fn decode_event(view_key: &ViewKey, bytes: &[u8]) -> Option<DecryptedNote> {
+    if bytes.len() < 8 {
+        return None;
+    }
+    let (discriminator, mut data) = bytes.split_at(8);
+
+    if discriminator == FundsLockedEvent::DISCRIMINATOR {
+        let event = FundsLockedEvent::deserialize(&mut data).ok()?;
+        let memo = view_key.decrypt_memo(event.encrypted_memo.as_deref()?).ok()?;
+        Some(DecryptedNote {
+            pool: event.pool,
+            amount: event.amount,
+            kind: NoteKind::Deposit {
+                commitment: event.commitment,
+                leaf_index: event.leaf_index,
+                locker: event.locker,
+            },
+            memo,
+        })
+    } else if discriminator == PrivacyPaymentExecutedEvent::DISCRIMINATOR {
+        let event = PrivacyPaymentExecutedEvent::deserialize(&mut data).ok()?;
+        let memo = view_key.decrypt_memo(event.encrypted_memo.as_deref()?).ok()?;
+        Some(DecryptedNote {
+            pool: event.pool,
+            amount: event.amount,
+            kind: NoteKind::Payment {
+                nullifier: event.nullifier,
+                to: event.to,
+                change_commitment: event.change_commitment,
+                change_leaf_index: event.change_leaf_index,
+                relayer_fee: event.relayer_fee,
+            },
+            memo,
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::Event;
+    use rand_core::OsRng;
+
+    fn invoke(program: &str) -> String {
+        format!("Program {} invoke [1]", program)
+    }
+
+    fn success(program: &str) -> String {
+        format!("Program {} success", program)
+    }
+
+    fn data_log<E: Event>(event: &E) -> String {
+        format!("Program data: {}", STANDARD.encode(event.data()))
+    }
+
+    fn funds_locked(memo: Option<Vec<u8>>) -> FundsLockedEvent {
+        FundsLockedEvent {
+            pool: Pubkey::new_unique(),
+            commitment: [1u8; 32],
+            leaf: [2u8; 32],
+            leaf_index: 7,
+            root: [3u8; 32],
+            amount: 1_000,
+            locker: Pubkey::new_unique(),
+            encrypted_memo: memo,
+        }
+    }
+
+    #[test]
+    fn memo_round_trip() {
+        let view_key = ViewKey::generate(&mut OsRng);
+        let memo = encrypt_memo(&mut OsRng, &view_key.public_key(), b"invoice #42").unwrap();
+
+        assert_eq!(memo.len(), EPHEMERAL_KEY_LEN + NONCE_LEN + b"invoice #42".len() + 16);
+        assert_eq!(view_key.decrypt_memo(&memo).unwrap(), b"invoice #42");
+    }
+
+    #[test]
+    fn memo_rejects_wrong_key() {
+        let view_key = ViewKey::generate(&mut OsRng);
+        let other = ViewKey::generate(&mut OsRng);
+        let memo = encrypt_memo(&mut OsRng, &view_key.public_key(), b"invoice #42").unwrap();
+
+        assert_eq!(other.decrypt_memo(&memo), Err(MemoError::DecryptionFailed));
+        assert_eq!(
+            view_key.decrypt_memo(&memo[..EPHEMERAL_KEY_LEN + NONCE_LEN - 1]),
+            Err(MemoError::Malformed)
+        );
+
+        let mut tampered = memo.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(view_key.decrypt_memo(&tampered), Err(MemoError::DecryptionFailed));
+    }
+
+    #[test]
+    fn scan_logs_decrypts_privacy_program_events() {
+        let view_key = ViewKey::generate(&mut OsRng);
+        let other = ViewKey::generate(&mut OsRng);
+        let program_id = diap_payment_privacy::ID.to_string();
+        let other_program = Pubkey::new_unique().to_string();
+
+        let deposit = funds_locked(Some(
+            encrypt_memo(&mut OsRng, &view_key.public_key(), b"deposit").unwrap(),
+        ));
+        let payment = PrivacyPaymentExecutedEvent {
+            pool: Pubkey::new_unique(),
+            nullifier: [4u8; 32],
+            to: Pubkey::new_unique(),
+            amount: 600,
+            change_commitment: [5u8; 32],
+            change_leaf_index: 8,
+            root: [6u8; 32],
+            relayer: Pubkey::new_unique(),
+            relayer_fee: 10,
+            encrypted_memo: Some(encrypt_memo(&mut OsRng, &view_key.public_key(), b"payment").unwrap()),
+        };
+        let not_ours = funds_locked(Some(
+            encrypt_memo(&mut OsRng, &other.public_key(), b"someone else").unwrap(),
+        ));
+        let no_memo = funds_locked(None);
+
+        let logs = vec![
+            invoke(&program_id),
+            "Program log: Instruction: LockFundsForPrivacy".to_string(),
+            data_log(&deposit),
+            data_log(&not_ours),
+            data_log(&no_memo),
+            success(&program_id),
+            // Look-alike event logged by another program is ignored
+            invoke(&other_program),
+            data_log(&deposit),
+            success(&other_program),
+            invoke(&program_id),
+            data_log(&payment),
+            success(&program_id),
+        ];
+
+        let notes = scan_logs(&view_key, &logs);
+        assert_eq!(
+            notes,
+            vec![
+                DecryptedNote {
+                    pool: deposit.pool,
+                    amount: deposit.amount,
+                    kind: NoteKind::Deposit {
+                        commitment: deposit.commitment,
+                        leaf_index: deposit.leaf_index,
+                        locker: deposit.locker,
+                    },
+                    memo: b"deposit".to_vec(),
+                },
+                DecryptedNote {
+                    pool: payment.pool,
+                    amount: payment.amount,
+                    kind: NoteKind::Payment {
+                        nullifier: payment.nullifier,
+                        to: payment.to,
+                        change_commitment: payment.change_commitment,
+                        change_leaf_index: payment.change_leaf_index,
+                        relayer_fee: payment.relayer_fee,
+                    },
+                    memo: b"payment".to_vec(),
+                },
+            ]
+        );
+    }
+}
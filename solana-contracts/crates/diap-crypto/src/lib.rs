@@ -0,0 +1,387 @@
+//! DIAP Crypto
+//!
+//! Proof and signature checks shared by the DIAP programs: Groth16 verification
+//! over BN254 with the alt_bn128 syscalls, and reading signatures out of the
+//! Ed25519 sigverify instruction that precedes the current one.
+
+use solana_program::account_info::AccountInfo;
+use solana_program::alt_bn128::prelude::{
+    alt_bn128_addition, alt_bn128_multiplication, alt_bn128_pairing,
+};
+use solana_program::ed25519_program;
+use solana_program::pubkey::Pubkey;
+use solana_program::sysvar::instructions::{load_current_index_checked, load_instruction_at_checked};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptoError {
+    /// Verifying key does not match the public inputs or holds an invalid point
+    InvalidVerifyingKey,
+    /// Public input is not a canonical scalar field element
+    InvalidPublicInput,
+    /// Proof point is malformed or the pairing check failed
+    InvalidProof,
+    /// The preceding instruction is not an Ed25519 sigverify instruction
+    MissingEd25519Instruction,
+    /// Ed25519 instruction is malformed or reads from other instructions
+    InvalidEd25519Instruction,
+}
+
+// ============ Groth16 ============
+
+/// BN254 base field modulus, big-endian
+pub const BN254_FIELD_MODULUS: [u8; 32] = [
+    0x30, 0x64, 0x4e, 0x72, 0xe1, 0x31, 0xa0, 0x29, 0xb8, 0x50, 0x45, 0xb6, 0x81, 0x81, 0x58, 0x5d,
+    0x97, 0x81, 0x6a, 0x91, 0x68, 0x71, 0xca, 0x8d, 0x3c, 0x20, 0x8c, 0x16, 0xd8, 0x7c, 0xfd, 0x47,
+];
+
+/// BN254 scalar field modulus, big-endian
+pub const BN254_SCALAR_MODULUS: [u8; 32] = [
+    0x30, 0x64, 0x4e, 0x72, 0xe1, 0x31, 0xa0, 0x29, 0xb8, 0x50, 0x45, 0xb6, 0x81, 0x81, 0x58, 0x5d,
+    0x28, 0x33, 0xe8, 0x48, 0x79, 0xb9, 0x70, 0x91, 0x43, 0xe1, 0xf5, 0x93, 0xf0, 0x00, 0x00, 0x01,
+];
+
+/// Groth16 verifying key points, in EIP-197 big-endian encoding
+pub struct Groth16VerifyingKey<'a> {
+    pub alpha_g1: &'a [u8; 64],
+    pub beta_g2: &'a [u8; 128],
+    pub gamma_g2: &'a [u8; 128],
+    pub delta_g2: &'a [u8; 128],
+    pub ic: &'a [[u8; 64]],
+}
+
+/// Groth16 proof points, in EIP-197 big-endian encoding
+pub struct Groth16ProofPoints<'a> {
+    pub a: &'a [u8; 64],
+    pub b: &'a [u8; 128],
+    pub c: &'a [u8; 64],
+}
+
+/// Check a Groth16 proof with the alt_bn128 syscalls:
+/// e(-A, B) * e(alpha, beta) * e(vk_x, gamma) * e(C, delta) == 1
+pub fn verify_groth16_proof(
+    verifying_key: &Groth16VerifyingKey,
+    proof: &Groth16ProofPoints,
+    public_inputs: &[[u8; 32]],
+) -> Result<(), CryptoError> {
+    if verifying_key.ic.len() != public_inputs.len() + 1 {
+        return Err(CryptoError::InvalidVerifyingKey);
+    }
+
+    // vk_x = IC[0] + sum(input_i * IC[i + 1])
+    let mut vk_x = verifying_key.ic[0];
+    for (input, ic) in public_inputs.iter().zip(verifying_key.ic[1..].iter()) {
+        if *input >= BN254_SCALAR_MODULUS {
+            return Err(CryptoError::InvalidPublicInput);
+        }
+
+        let product = alt_bn128_multiplication(&[&ic[..], &input[..]].concat())
+            .map_err(|_| CryptoError::InvalidVerifyingKey)?;
+        let sum = alt_bn128_addition(&[&vk_x[..], &product[..]].concat())
+            .map_err(|_| CryptoError::InvalidVerifyingKey)?;
+        vk_x.copy_from_slice(&sum);
+    }
+
+    let pairing_input = [
+        &negate_g1(proof.a)?[..],
+        &proof.b[..],
+        &verifying_key.alpha_g1[..],
+        &verifying_key.beta_g2[..],
+        &vk_x[..],
+        &verifying_key.gamma_g2[..],
+        &proof.c[..],
+        &verifying_key.delta_g2[..],
+    ]
+    .concat();
+    let result = alt_bn128_pairing(&pairing_input).map_err(|_| CryptoError::InvalidProof)?;
+
+    let mut one = [0u8; 32];
+    one[31] = 1;
+    if result[..] != one[..] {
+        return Err(CryptoError::InvalidProof);
+    }
+
+    Ok(())
+}
+
+/// Negate a G1 point by replacing y with p - y
+pub fn negate_g1(point: &[u8; 64]) -> Result<[u8; 64], CryptoError> {
+    if point.iter().all(|b| *b == 0) {
+        return Ok(*point);
+    }
+
+    let mut y = [0u8; 32];
+    y.copy_from_slice(&point[32..]);
+    if y >= BN254_FIELD_MODULUS {
+        return Err(CryptoError::InvalidProof);
+    }
+
+    let mut negated = *point;
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let mut diff = BN254_FIELD_MODULUS[i] as i16 - y[i] as i16 - borrow;
+        borrow = 0;
+        if diff < 0 {
+            diff += 256;
+            borrow = 1;
+        }
+        negated[32 + i] = diff as u8;
+    }
+
+    Ok(negated)
+}
+
+// ============ Ed25519 ============
+
+const ED25519_OFFSETS_START: usize = 2;
+const ED25519_OFFSETS_SIZE: usize = 14;
+
+/// One signature entry of an Ed25519 sigverify instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ed25519Signature {
+    pub signature: [u8; 64],
+    pub public_key: Pubkey,
+    pub message: Vec<u8>,
+}
+
+/// Require the instruction preceding the current one to be an Ed25519 sigverify
+/// instruction and return the signatures it verified.
+pub fn load_ed25519_signatures(instructions: &AccountInfo) -> Result<Vec<Ed25519Signature>, CryptoError> {
+    let current_index =
+        load_current_index_checked(instructions).map_err(|_| CryptoError::MissingEd25519Instruction)?;
+    if current_index == 0 {
+        return Err(CryptoError::MissingEd25519Instruction);
+    }
+
+    let ix = load_instruction_at_checked((current_index - 1) as usize, instructions)
+        .map_err(|_| CryptoError::MissingEd25519Instruction)?;
+    if ix.program_id != ed25519_program::ID {
+        return Err(CryptoError::MissingEd25519Instruction);
+    }
+    if ix.data.len() < ED25519_OFFSETS_START {
+        return Err(CryptoError::InvalidEd25519Instruction);
+    }
+
+    (0..ix.data[0] as usize)
+        .map(|index| read_ed25519_signature(&ix.data, index))
+        .collect()
+}
+
+/// Read the `index`-th entry of an Ed25519 instruction. Only self-contained
+/// signatures are accepted, so the bytes read here are exactly the bytes the
+/// precompile verified.
+fn read_ed25519_signature(data: &[u8], index: usize) -> Result<Ed25519Signature, CryptoError> {
+    let start = ED25519_OFFSETS_START + index * ED25519_OFFSETS_SIZE;
+    let offsets = data
+        .get(start..start + ED25519_OFFSETS_SIZE)
+        .ok_or(CryptoError::InvalidEd25519Instruction)?;
+    let read_u16 = |at: usize| u16::from_le_bytes([offsets[at], offsets[at + 1]]);
+
+    let signature_offset = read_u16(0) as usize;
+    let signature_ix = read_u16(2);
+    let public_key_offset = read_u16(4) as usize;
+    let public_key_ix = read_u16(6);
+    let message_offset = read_u16(8) as usize;
+    let message_size = read_u16(10) as usize;
+    let message_ix = read_u16(12);
+
+    if signature_ix != u16::MAX || public_key_ix != u16::MAX || message_ix != u16::MAX {
+        return Err(CryptoError::InvalidEd25519Instruction);
+    }
+
+    let signature = data
+        .get(signature_offset..signature_offset + 64)
+        .ok_or(CryptoError::InvalidEd25519Instruction)?;
+    let public_key = data
+        .get(public_key_offset..public_key_offset + 32)
+        .ok_or(CryptoError::InvalidEd25519Instruction)?;
+    let message = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(CryptoError::InvalidEd25519Instruction)?;
+
+    let mut signature_bytes = [0u8; 64];
+    signature_bytes.copy_from_slice(signature);
+    let mut public_key_bytes = [0u8; 32];
+    public_key_bytes.copy_from_slice(public_key);
+
+    Ok(Ed25519Signature {
+        signature: signature_bytes,
+        public_key: Pubkey::new_from_array(public_key_bytes),
+        message: message.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::sysvar::instructions::{
+        construct_instructions_data, store_current_index, BorrowedInstruction,
+    };
+    use solana_program::sysvar;
+
+    const MESSAGE: &[u8] = b"diap state";
+
+    /// Single self-contained Ed25519 entry laid out the way the precompile expects
+    fn ed25519_data(signature: [u8; 64], public_key: [u8; 32], message: &[u8]) -> Vec<u8> {
+        let public_key_offset = (ED25519_OFFSETS_START + ED25519_OFFSETS_SIZE) as u16;
+        let signature_offset = public_key_offset + 32;
+        let message_offset = signature_offset + 64;
+
+        let mut data = vec![1, 0];
+        for value in [
+            signature_offset,
+            u16::MAX,
+            public_key_offset,
+            u16::MAX,
+            message_offset,
+            message.len() as u16,
+            u16::MAX,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&public_key);
+        data.extend_from_slice(&signature);
+        data.extend_from_slice(message);
+        data
+    }
+
+    /// Run `check` against an instructions sysvar holding `previous` followed by
+    /// the current instruction
+    fn with_instructions<T>(
+        previous_program: &Pubkey,
+        previous_data: &[u8],
+        check: impl FnOnce(&AccountInfo) -> T,
+    ) -> T {
+        let current_program = Pubkey::new_unique();
+        let instructions = [
+            BorrowedInstruction {
+                program_id: previous_program,
+                accounts: vec![],
+                data: previous_data,
+            },
+            BorrowedInstruction {
+                program_id: &current_program,
+                accounts: vec![],
+                data: &[],
+            },
+        ];
+        let mut data = construct_instructions_data(&instructions);
+        store_current_index(&mut data, 1);
+
+        let key = sysvar::instructions::id();
+        let owner = sysvar::id();
+        let mut lamports = 0;
+        let account = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+        check(&account)
+    }
+
+    #[test]
+    fn loads_self_contained_signature() {
+        let public_key = Pubkey::new_unique();
+        let data = ed25519_data([9u8; 64], public_key.to_bytes(), MESSAGE);
+
+        let signatures = with_instructions(&ed25519_program::ID, &data, |account| {
+            load_ed25519_signatures(account)
+        })
+        .unwrap();
+
+        assert_eq!(
+            signatures,
+            vec![Ed25519Signature {
+                signature: [9u8; 64],
+                public_key,
+                message: MESSAGE.to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_other_previous_instruction() {
+        let data = ed25519_data([9u8; 64], [1u8; 32], MESSAGE);
+
+        let result = with_instructions(&Pubkey::new_unique(), &data, |account| {
+            load_ed25519_signatures(account)
+        });
+
+        assert_eq!(result, Err(CryptoError::MissingEd25519Instruction));
+    }
+
+    #[test]
+    fn rejects_first_instruction() {
+        let key = sysvar::instructions::id();
+        let owner = sysvar::id();
+        let mut data = construct_instructions_data(&[BorrowedInstruction {
+            program_id: &ed25519_program::ID,
+            accounts: vec![],
+            data: &[],
+        }]);
+        store_current_index(&mut data, 0);
+        let mut lamports = 0;
+        let account = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+
+        assert_eq!(load_ed25519_signatures(&account), Err(CryptoError::MissingEd25519Instruction));
+    }
+
+    #[test]
+    fn rejects_offsets_into_other_instructions() {
+        // Signature, public key and message instruction indexes sit at these offsets
+        for field in [4, 8, 14] {
+            let mut data = ed25519_data([9u8; 64], [1u8; 32], MESSAGE);
+            data[field..field + 2].copy_from_slice(&0u16.to_le_bytes());
+
+            let result = with_instructions(&ed25519_program::ID, &data, |account| {
+                load_ed25519_signatures(account)
+            });
+
+            assert_eq!(result, Err(CryptoError::InvalidEd25519Instruction), "field at {}", field);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_payload() {
+        let full = ed25519_data([9u8; 64], [1u8; 32], MESSAGE);
+        let mut missing_entry = full.clone();
+        missing_entry[0] = 2;
+
+        for data in [
+            vec![1],
+            full[..ED25519_OFFSETS_START + ED25519_OFFSETS_SIZE - 1].to_vec(),
+            full[..full.len() - 1].to_vec(),
+            missing_entry,
+        ] {
+            let result = with_instructions(&ed25519_program::ID, &data, |account| {
+                load_ed25519_signatures(account)
+            });
+
+            assert_eq!(result, Err(CryptoError::InvalidEd25519Instruction), "data len {}", data.len());
+        }
+    }
+
+    #[test]
+    fn negates_g1_generator() {
+        let mut generator = [0u8; 64];
+        generator[31] = 1;
+        generator[63] = 2;
+
+        let negated = negate_g1(&generator).unwrap();
+
+        let mut expected = generator;
+        expected[32..].copy_from_slice(&BN254_FIELD_MODULUS);
+        expected[63] -= 2;
+        assert_eq!(negated, expected);
+        assert_eq!(negate_g1(&negated).unwrap(), generator);
+    }
+
+    #[test]
+    fn negates_point_at_infinity_to_itself() {
+        assert_eq!(negate_g1(&[0u8; 64]).unwrap(), [0u8; 64]);
+    }
+
+    #[test]
+    fn rejects_non_canonical_y() {
+        let mut point = [0u8; 64];
+        point[31] = 1;
+        point[32..].copy_from_slice(&BN254_FIELD_MODULUS);
+
+        assert_eq!(negate_g1(&point), Err(CryptoError::InvalidProof));
+    }
+}
@@ -5,8 +5,11 @@
 //!
 //! Adapted from Solidity DIAPAgentNetwork.sol to Solana/Anchor.
 
+#![allow(unexpected_cfgs)]
+
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::keccak;
+use anchor_lang::{system_program, Discriminator};
 use anchor_spl::token::{self, TokenAccount, Transfer, Mint, Token};
 
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
@@ -16,6 +19,7 @@ pub mod diap_agent_network {
     use super::*;
 
     /// Initialize the agent network program
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize(
         ctx: Context<Initialize>,
         token_mint: Pubkey,
@@ -43,6 +47,7 @@ pub mod diap_agent_network {
         network.total_volume = 0;
         network.total_staked = 0;
         network.accumulated_fees = 0;
+        network.slash_rate = 0;
         network.bump = ctx.bumps.network;
 
         Ok(())
@@ -91,6 +96,7 @@ pub mod diap_agent_network {
         agent.total_services = 0;
         agent.is_active = true;
         agent.is_verified = false;
+        agent.slashed_blacklist_at = 0;
         agent.bump = ctx.bumps.agent;
         let agent_authority = agent.authority; // Store for later use
 
@@ -126,6 +132,12 @@ pub mod diap_agent_network {
             ErrorCode::LockPeriodNotEnded
         );
 
+        // Stake stays locked while the agent is blacklisted or has reports awaiting moderation
+        if let Some(record) = load_agent_record(&ctx.accounts.agent_record)? {
+            require!(!record.is_blacklisted_at(clock.unix_timestamp), ErrorCode::AgentBlacklisted);
+            require!(!record.has_pending_reports_at(clock.unix_timestamp), ErrorCode::ReportPending);
+        }
+
         let staked_amount = agent.staked_amount;
         agent.is_active = false;
 
@@ -187,8 +199,9 @@ pub mod diap_agent_network {
         let network = &ctx.accounts.network;
 
         require!(agent.is_active, ErrorCode::AgentNotRegistered);
-        require!(message_cid.len() > 0, ErrorCode::InvalidMessageCID);
+        require!(!message_cid.is_empty(), ErrorCode::InvalidMessageCID);
         require!(agent.authority == ctx.accounts.signer.key(), ErrorCode::Unauthorized);
+        require!(!is_agent_blacklisted(&ctx.accounts.agent_record)?, ErrorCode::AgentBlacklisted);
 
         let clock = Clock::get()?;
         let message_id = keccak::hashv(&[
@@ -245,9 +258,10 @@ pub mod diap_agent_network {
 
         require!(agent.is_verified, ErrorCode::AgentNotVerified);
         require!(price > 0, ErrorCode::InvalidPrice);
-        require!(service_type.len() > 0, ErrorCode::ServiceTypeRequired);
+        require!(!service_type.is_empty(), ErrorCode::ServiceTypeRequired);
         require!(agent.authority == ctx.accounts.signer.key(), ErrorCode::Unauthorized);
         require!(consumer != agent.authority, ErrorCode::CannotCreateServiceForSelf);
+        require!(!is_agent_blacklisted(&ctx.accounts.agent_record)?, ErrorCode::AgentBlacklisted);
 
         let clock = Clock::get()?;
 
@@ -284,8 +298,9 @@ pub mod diap_agent_network {
 
         require!(service.provider == agent.authority, ErrorCode::NotServiceProvider);
         require!(!service.is_completed, ErrorCode::ServiceAlreadyCompleted);
-        require!(result_cid.len() > 0, ErrorCode::InvalidResultCID);
+        require!(!result_cid.is_empty(), ErrorCode::InvalidResultCID);
         require!(agent.authority == ctx.accounts.signer.key(), ErrorCode::Unauthorized);
+        require!(!is_agent_blacklisted(&ctx.accounts.agent_record)?, ErrorCode::AgentBlacklisted);
 
         let clock = Clock::get()?;
         require!(
@@ -367,6 +382,49 @@ pub mod diap_agent_network {
         Ok(())
     }
 
+    /// Set the share of stake slashed from blacklisted agents, in basis points (authority only)
+    pub fn set_slash_rate(ctx: Context<UpdateNetworkParams>, slash_rate: u16) -> Result<()> {
+        require!(slash_rate <= 10000, ErrorCode::InvalidSlashRate);
+        ctx.accounts.network.slash_rate = slash_rate;
+
+        Ok(())
+    }
+
+    /// Slash the stake of an agent blacklisted in diap-verification.
+    /// Permissionless; does nothing unless the authority has set a `slash_rate`.
+    pub fn slash_blacklisted_agent(ctx: Context<SlashBlacklistedAgent>) -> Result<()> {
+        let agent = &mut ctx.accounts.agent;
+        let network = &mut ctx.accounts.network;
+
+        require!(network.slash_rate > 0, ErrorCode::SlashingDisabled);
+        require!(agent.is_active, ErrorCode::AgentNotRegistered);
+        let blacklisted_at = active_blacklisting(&ctx.accounts.agent_record)?
+            .ok_or(ErrorCode::AgentNotBlacklisted)?;
+        require!(agent.slashed_blacklist_at != blacklisted_at, ErrorCode::AgentAlreadySlashed);
+
+        let slashed_amount = agent.staked_amount
+            .checked_mul(network.slash_rate as u64)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(10000)
+            .ok_or(ErrorCode::MathDivision)?;
+
+        // Slashed stake stays in the network token account and becomes withdrawable fees
+        agent.staked_amount = agent.staked_amount.checked_sub(slashed_amount).ok_or(ErrorCode::MathUnderflow)?;
+        agent.slashed_blacklist_at = blacklisted_at;
+
+        network.total_staked = network.total_staked.checked_sub(slashed_amount).ok_or(ErrorCode::MathUnderflow)?;
+        network.accumulated_fees = network.accumulated_fees.checked_add(slashed_amount).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(AgentSlashedEvent {
+            agent: agent.authority,
+            slashed_amount,
+            remaining_stake: agent.staked_amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
     /// Withdraw accumulated fees to treasury
     pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: u64) -> Result<()> {
         let network = &mut ctx.accounts.network;
@@ -400,6 +458,50 @@ pub mod diap_agent_network {
 
         Ok(())
     }
+
+    /// Grow a `NetworkState` created before `slash_rate` was added (authority only).
+    /// The rate starts at 0, leaving slashing disabled.
+    pub fn migrate_network(ctx: Context<MigrateNetwork>) -> Result<()> {
+        let info = ctx.accounts.network.to_account_info();
+        require!(info.data_len() < 8 + NetworkState::LEN, ErrorCode::AlreadyMigrated);
+        {
+            // `authority` is the first field in every layout
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 + 32 && data[..8] == NetworkState::DISCRIMINATOR,
+                ErrorCode::InvalidLegacyAccount
+            );
+            require!(data[8..40] == ctx.accounts.authority.key().to_bytes(), ErrorCode::Unauthorized);
+        }
+
+        grow_account(
+            &info,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.system_program,
+            8 + NetworkState::LEN,
+        )
+    }
+
+    /// Grow an `Agent` created before `slashed_blacklist_at` was added.
+    /// Permissionless; the field starts at 0, so no blacklisting counts as slashed.
+    pub fn migrate_agent(ctx: Context<MigrateAgent>, _authority: Pubkey) -> Result<()> {
+        let info = ctx.accounts.agent.to_account_info();
+        require!(info.data_len() < 8 + Agent::LEN, ErrorCode::AlreadyMigrated);
+        {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == Agent::DISCRIMINATOR,
+                ErrorCode::InvalidLegacyAccount
+            );
+        }
+
+        grow_account(
+            &info,
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.system_program,
+            8 + Agent::LEN,
+        )
+    }
 }
 
 // ============ Accounts ============
@@ -503,6 +605,14 @@ pub struct UnstakeAgent<'info> {
         constraint = network_token_account.key() == get_network_token_account(&network.token_mint)
     )]
     pub network_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: diap-verification AgentRecord PDA; may be uninitialized
+    #[account(
+        seeds = [b"agent", agent.authority.as_ref()],
+        bump,
+        seeds::program = diap_verification::ID
+    )]
+    pub agent_record: UncheckedAccount<'info>,
     
     pub token_program: Program<'info, Token>,
 }
@@ -544,6 +654,14 @@ pub struct SendMessage<'info> {
     pub message: Account<'info, Message>,
     /// CHECK: This is the message ID PDA
     pub message_id: UncheckedAccount<'info>,
+
+    /// CHECK: diap-verification AgentRecord PDA; may be uninitialized
+    #[account(
+        seeds = [b"agent", agent.authority.as_ref()],
+        bump,
+        seeds::program = diap_verification::ID
+    )]
+    pub agent_record: UncheckedAccount<'info>,
     
     #[account(
         mut,
@@ -588,6 +706,14 @@ pub struct CreateService<'info> {
         bump
     )]
     pub service: Account<'info, Service>,
+
+    /// CHECK: diap-verification AgentRecord PDA; may be uninitialized
+    #[account(
+        seeds = [b"agent", agent.authority.as_ref()],
+        bump,
+        seeds::program = diap_verification::ID
+    )]
+    pub agent_record: UncheckedAccount<'info>,
     
     #[account(mut)]
     pub signer: Signer<'info>,
@@ -617,6 +743,14 @@ pub struct CompleteService<'info> {
         bump = service.bump
     )]
     pub service: Account<'info, Service>,
+
+    /// CHECK: diap-verification AgentRecord PDA; may be uninitialized
+    #[account(
+        seeds = [b"agent", agent.authority.as_ref()],
+        bump,
+        seeds::program = diap_verification::ID
+    )]
+    pub agent_record: UncheckedAccount<'info>,
     
     #[account(
         mut,
@@ -637,6 +771,31 @@ pub struct CompleteService<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+#[derive(Accounts)]
+pub struct SlashBlacklistedAgent<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent", agent.authority.as_ref()],
+        bump = agent.bump
+    )]
+    pub agent: Account<'info, Agent>,
+    
+    #[account(
+        mut,
+        seeds = [b"network", network.token_mint.as_ref()],
+        bump = network.bump
+    )]
+    pub network: Account<'info, NetworkState>,
+
+    /// CHECK: diap-verification AgentRecord PDA; may be uninitialized
+    #[account(
+        seeds = [b"agent", agent.authority.as_ref()],
+        bump,
+        seeds::program = diap_verification::ID
+    )]
+    pub agent_record: UncheckedAccount<'info>,
+}
+
 #[derive(Accounts)]
 pub struct UpdateNetworkParams<'info> {
     #[account(
@@ -681,6 +840,43 @@ pub struct WithdrawFees<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+#[derive(Accounts)]
+pub struct MigrateNetwork<'info> {
+    /// CHECK: Pre-upgrade `NetworkState`, too short to deserialize as the current layout
+    #[account(
+        mut,
+        seeds = [b"network", token_mint.key().as_ref()],
+        bump,
+        owner = crate::ID
+    )]
+    pub network: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub token_mint: Account<'info, Mint>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(authority: Pubkey)]
+pub struct MigrateAgent<'info> {
+    /// CHECK: Pre-upgrade `Agent`, sized for the old layout
+    #[account(
+        mut,
+        seeds = [b"agent", authority.as_ref()],
+        bump,
+        owner = crate::ID
+    )]
+    pub agent: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
 // ============ State ============
 
 #[account]
@@ -701,10 +897,11 @@ pub struct NetworkState {
     pub total_staked: u64,
     pub accumulated_fees: u64,
     pub bump: u8,
+    pub slash_rate: u16, // Basis points of stake slashed on blacklisting, 0 = disabled
 }
 
 impl NetworkState {
-    pub const LEN: usize = 32 + 32 + 8 + 8 + 2 + 8 + 8 + 8 + 2 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 2 + 8 + 8 + 8 + 2 + 8 + 8 + 8 + 8 + 8 + 8 + 1 + 2;
 }
 
 #[account]
@@ -721,10 +918,11 @@ pub struct Agent {
     pub is_active: bool,
     pub is_verified: bool,
     pub bump: u8,
+    pub slashed_blacklist_at: i64, // Blacklisting the stake was last slashed for
 }
 
 impl Agent {
-    pub const LEN: usize = 32 + 100 + 100 + 8 + 8 + 8 + 8 + 8 + 4 + 1 + 1 + 1;
+    pub const LEN: usize = 32 + 100 + 100 + 8 + 8 + 8 + 8 + 8 + 4 + 1 + 1 + 1 + 8;
 }
 
 #[account]
@@ -817,6 +1015,14 @@ pub struct ServiceCompletedEvent {
     pub reward: u64,
 }
 
+#[event]
+pub struct AgentSlashedEvent {
+    pub agent: Pubkey,
+    pub slashed_amount: u64,
+    pub remaining_stake: u64,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct FeesWithdrawnEvent {
     pub to: Pubkey,
@@ -866,12 +1072,30 @@ pub enum ErrorCode {
     InvalidAmount,
     #[msg("Insufficient fees")]
     InsufficientFees,
+    #[msg("Agent is blacklisted")]
+    AgentBlacklisted,
+    #[msg("Agent is not blacklisted")]
+    AgentNotBlacklisted,
+    #[msg("Agent stake already slashed")]
+    AgentAlreadySlashed,
+    #[msg("Slashing is disabled")]
+    SlashingDisabled,
+    #[msg("Invalid slash rate")]
+    InvalidSlashRate,
+    #[msg("Invalid agent record")]
+    InvalidAgentRecord,
     #[msg("Math overflow")]
     MathOverflow,
     #[msg("Math underflow")]
     MathUnderflow,
     #[msg("Math division error")]
     MathDivision,
+    #[msg("Agent has reports pending moderation")]
+    ReportPending,
+    #[msg("Account already migrated")]
+    AlreadyMigrated,
+    #[msg("Account is not a valid pre-upgrade account")]
+    InvalidLegacyAccount,
 }
 
 // ============ Utilities ============
@@ -883,3 +1107,57 @@ fn _is_identifier_used(identifiers: &[String; 100], identifier: &str) -> bool {
 fn get_network_token_account(token_mint: &Pubkey) -> Pubkey {
     Pubkey::find_program_address(&[b"network-token", token_mint.as_ref()], &ID).0
 }
+
+/// Top up rent from `payer` and zero-extend `account` to `new_len`
+fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+    new_len: usize,
+) -> Result<()> {
+    let required = Rent::get()?.minimum_balance(new_len);
+    let shortfall = required.saturating_sub(account.lamports());
+    if shortfall > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+    account.realloc(new_len, true)?;
+
+    Ok(())
+}
+
+fn is_agent_blacklisted(agent_record: &AccountInfo) -> Result<bool> {
+    Ok(active_blacklisting(agent_record)?.is_some())
+}
+
+/// Return when the agent's current diap-verification blacklisting started, or
+/// `None` if it is not blacklisted or the entry expired.
+fn active_blacklisting(agent_record: &AccountInfo) -> Result<Option<i64>> {
+    let Some(record) = load_agent_record(agent_record)? else {
+        return Ok(None);
+    };
+    let now = Clock::get()?.unix_timestamp;
+    Ok(record.is_blacklisted_at(now).then_some(record.blacklisted_at))
+}
+
+/// Read the agent's diap-verification `AgentRecord`. Agents that were never
+/// verified or reported have no record.
+fn load_agent_record(agent_record: &AccountInfo) -> Result<Option<diap_verification::AgentRecord>> {
+    if agent_record.data_is_empty() {
+        return Ok(None);
+    }
+    require!(agent_record.owner == &diap_verification::ID, ErrorCode::InvalidAgentRecord);
+
+    let data = agent_record.try_borrow_data()?;
+    let record = diap_verification::AgentRecord::try_deserialize(&mut &data[..])
+        .map_err(|_| error!(ErrorCode::InvalidAgentRecord))?;
+    Ok(Some(record))
+}
@@ -6,7 +6,12 @@
 #![allow(unexpected_cfgs)]
 
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::keccak;
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use anchor_lang::{system_program, Discriminator};
+use anchor_spl::token::{self, CloseAccount, Mint, Token, TokenAccount, Transfer};
+use diap_crypto::{load_ed25519_signatures, CryptoError, Ed25519Signature};
 
 declare_id!("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin");
 
@@ -17,11 +22,20 @@ pub mod diap_payment_channel {
     pub fn initialize(
         ctx: Context<Initialize>,
         channel_fee_rate: u16,
+        min_dispute_period: i64,
+        max_dispute_period: i64,
     ) -> Result<()> {
+        require!(
+            min_dispute_period > 0 && min_dispute_period <= max_dispute_period,
+            ErrorCode::InvalidDisputePeriodBounds
+        );
+
         let payment_channel = &mut ctx.accounts.payment_channel;
         payment_channel.authority = ctx.accounts.authority.key();
         payment_channel.token_mint = ctx.accounts.token_mint.key();
         payment_channel.channel_fee_rate = channel_fee_rate;
+        payment_channel.min_dispute_period = min_dispute_period;
+        payment_channel.max_dispute_period = max_dispute_period;
         payment_channel.bump = ctx.bumps.payment_channel;
 
         Ok(())
@@ -32,11 +46,17 @@ pub mod diap_payment_channel {
         participant2: Pubkey,
         deposit: u64,
         channel_id: String,
+        dispute_period: i64,
     ) -> Result<()> {
         require!(deposit > 0, ErrorCode::DepositMustBeGreaterThanZero);
-        require!(channel_id.len() > 0, ErrorCode::ChannelIDRequired);
+        require!(!channel_id.is_empty(), ErrorCode::ChannelIDRequired);
         require!(!ctx.accounts.channel.is_initialized, ErrorCode::ChannelAlreadyExists);
         require!(participant2 != ctx.accounts.participant1.key(), ErrorCode::CannotOpenChannelWithSelf);
+        require!(
+            dispute_period >= ctx.accounts.payment_channel.min_dispute_period
+                && dispute_period <= ctx.accounts.payment_channel.max_dispute_period,
+            ErrorCode::InvalidDisputePeriod
+        );
 
         let clock = Clock::get()?;
         
@@ -47,10 +67,21 @@ pub mod diap_payment_channel {
         channel.balance1 = deposit;
         channel.balance2 = 0;
         channel.total_deposited = deposit;
+        channel.deposited1 = deposit;
+        channel.deposited2 = 0;
         channel.nonce = 0;
         channel.is_active = true;
         channel.last_update = clock.unix_timestamp;
         channel.challenge_deadline = 0;
+        channel.dispute_period = dispute_period;
+        channel.htlcs = Vec::new();
+        channel.close_initiator = Pubkey::default();
+        channel.watchtower1 = None;
+        channel.watchtower2 = None;
+        channel.watchtower_bounty_bps1 = 0;
+        channel.watchtower_bounty_bps2 = 0;
+        channel.bounty_recipient = None;
+        channel.bounty_bps = 0;
         channel.channel_id = channel_id.clone();
         channel.bump = ctx.bumps.channel;
         channel.is_initialized = true;
@@ -75,35 +106,162 @@ pub mod diap_payment_channel {
         Ok(())
     }
 
+    /// Top up an open channel from either participant's side.
+    /// Signed states carry the per-side deposit totals they account for; anything
+    /// deposited after a state was signed is credited back to the depositor when it settles.
+    pub fn deposit_to_channel(ctx: Context<DepositToChannel>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::DepositMustBeGreaterThanZero);
+
+        let channel = &mut ctx.accounts.channel;
+        let depositor = ctx.accounts.depositor.key();
+
+        require!(channel.is_initialized, ErrorCode::ChannelNotFound);
+        require!(channel.is_active, ErrorCode::ChannelNotActive);
+        require!(channel.challenge_deadline == 0, ErrorCode::ChannelClosing);
+
+        if depositor == channel.participant1 {
+            channel.balance1 = channel.balance1.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+            channel.deposited1 = channel.deposited1.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        } else if depositor == channel.participant2 {
+            channel.balance2 = channel.balance2.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+            channel.deposited2 = channel.deposited2.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        } else {
+            return Err(ErrorCode::NotChannelParticipant.into());
+        }
+        channel.total_deposited = channel.total_deposited.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        channel.last_update = Clock::get()?.unix_timestamp;
+
+        // Transfer deposit from participant to channel vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.depositor_token_account.to_account_info(),
+            to: ctx.accounts.channel_vault.to_account_info(),
+            authority: ctx.accounts.depositor.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        emit!(ChannelDepositEvent {
+            channel_id: channel.channel_id.clone(),
+            depositor,
+            amount,
+            total_deposited: channel.total_deposited,
+        });
+
+        Ok(())
+    }
+
+    /// Register, replace or clear the watchtower allowed to challenge closes on the
+    /// signing participant's behalf. Needs no consent from the counter-party it guards against.
+    pub fn register_watchtower(
+        ctx: Context<RegisterWatchtower>,
+        watchtower: Option<Pubkey>,
+    ) -> Result<()> {
+        let channel = &mut ctx.accounts.channel;
+        let participant = ctx.accounts.participant.key();
+
+        require!(channel.is_initialized, ErrorCode::ChannelNotFound);
+        require!(channel.is_active, ErrorCode::ChannelNotActive);
+
+        let bounty_bps = if participant == channel.participant1 {
+            channel.watchtower1 = watchtower;
+            channel.watchtower_bounty_bps1
+        } else if participant == channel.participant2 {
+            channel.watchtower2 = watchtower;
+            channel.watchtower_bounty_bps2
+        } else {
+            return Err(ErrorCode::NotChannelParticipant.into());
+        };
+
+        emit!(WatchtowerRegisteredEvent {
+            channel_id: channel.channel_id.clone(),
+            participant,
+            watchtower,
+            bounty_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Set the bounty (in basis points of a cheating counter-party's balance) earned by
+    /// `participant`'s watchtower. Both participants sign, since the bounty is paid by
+    /// whichever side cheats; without it the watchtower still challenges, unpaid.
+    pub fn set_watchtower_bounty(
+        ctx: Context<SetWatchtowerBounty>,
+        participant: Pubkey,
+        bounty_bps: u16,
+    ) -> Result<()> {
+        let channel = &mut ctx.accounts.channel;
+
+        require!(channel.is_initialized, ErrorCode::ChannelNotFound);
+        require!(channel.is_active, ErrorCode::ChannelNotActive);
+        require!(channel.challenge_deadline == 0, ErrorCode::ChannelClosing);
+        require!(bounty_bps <= MAX_WATCHTOWER_BOUNTY_BPS, ErrorCode::BountyTooHigh);
+
+        if participant == channel.participant1 {
+            channel.watchtower_bounty_bps1 = bounty_bps;
+        } else if participant == channel.participant2 {
+            channel.watchtower_bounty_bps2 = bounty_bps;
+        } else {
+            return Err(ErrorCode::NotChannelParticipant.into());
+        }
+
+        emit!(WatchtowerBountySetEvent {
+            channel_id: channel.channel_id.clone(),
+            participant,
+            bounty_bps,
+        });
+
+        Ok(())
+    }
+
     pub fn initiate_channel_close(
         ctx: Context<InitiateChannelClose>,
         final_balance1: u64,
         final_balance2: u64,
+        deposited1: u64,
+        deposited2: u64,
         nonce: u64,
+        htlcs: Vec<Htlc>,
     ) -> Result<()> {
         let channel = &mut ctx.accounts.channel;
         
         require!(channel.is_initialized, ErrorCode::ChannelNotFound);
         require!(channel.is_active, ErrorCode::ChannelNotActive);
+        // Newer states during a dispute go through `challenge_channel_close`
+        require!(channel.challenge_deadline == 0, ErrorCode::ChannelClosing);
         require!(
             ctx.accounts.signer.key() == channel.participant1 || 
             ctx.accounts.signer.key() == channel.participant2,
             ErrorCode::NotChannelParticipant
         );
         require!(nonce > channel.nonce, ErrorCode::NonceMustBeGreater);
-        
-        let total_final = final_balance1
-            .checked_add(final_balance2)
-            .ok_or(ErrorCode::MathOverflow)?;
-        require!(total_final <= channel.total_deposited, ErrorCode::InvalidBalanceDistribution);
 
-        // Note: Signature verification would happen here in a full implementation
-        // For this simplified version, we assume the balances are agreed upon
+        // The submitted state must carry the counter-party's signature
+        let counterparty = counterparty_of(channel, ctx.accounts.signer.key());
+        let message = channel_state_message(
+            &channel.channel_id,
+            nonce,
+            final_balance1,
+            final_balance2,
+            deposited1,
+            deposited2,
+            &htlcs,
+        );
+        verify_ed25519_signatures(&ctx.accounts.instructions, &[counterparty], &message)?;
+
+        let locked = validate_htlcs(&htlcs)?;
+        let (final_balance1, final_balance2) =
+            settle_signed_balances(channel, final_balance1, final_balance2, deposited1, deposited2, locked)?;
 
         channel.balance1 = final_balance1;
         channel.balance2 = final_balance2;
+        channel.htlcs = htlcs;
         channel.nonce = nonce;
-        channel.challenge_deadline = Clock::get()?.unix_timestamp + (24 * 60 * 60); // 24 hours
+        channel.close_initiator = ctx.accounts.signer.key();
+        channel.challenge_deadline = Clock::get()?.unix_timestamp
+            .checked_add(channel.dispute_period)
+            .ok_or(ErrorCode::MathOverflow)?;
 
         emit!(PaymentChannelClosedEvent {
             channel_id: channel.channel_id.clone(),
@@ -114,11 +272,43 @@ pub mod diap_payment_channel {
         Ok(())
     }
 
+    /// Start the dispute window on the state already recorded on-chain (the opening
+    /// deposits at nonce 0) without the counter-party's signature, so an unresponsive
+    /// counter-party cannot lock the funds. Any newer co-signed state can still challenge it.
+    pub fn force_close_channel(ctx: Context<ForceCloseChannel>) -> Result<()> {
+        let channel = &mut ctx.accounts.channel;
+
+        require!(channel.is_initialized, ErrorCode::ChannelNotFound);
+        require!(channel.is_active, ErrorCode::ChannelNotActive);
+        require!(channel.challenge_deadline == 0, ErrorCode::ChannelClosing);
+        require!(
+            ctx.accounts.signer.key() == channel.participant1 ||
+            ctx.accounts.signer.key() == channel.participant2,
+            ErrorCode::NotChannelParticipant
+        );
+
+        channel.close_initiator = ctx.accounts.signer.key();
+        channel.challenge_deadline = Clock::get()?.unix_timestamp
+            .checked_add(channel.dispute_period)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(PaymentChannelClosedEvent {
+            channel_id: channel.channel_id.clone(),
+            final_balance1: channel.balance1,
+            final_balance2: channel.balance2,
+        });
+
+        Ok(())
+    }
+
     pub fn challenge_channel_close(
         ctx: Context<ChallengeChannelClose>,
         new_balance1: u64,
         new_balance2: u64,
+        new_deposited1: u64,
+        new_deposited2: u64,
         new_nonce: u64,
+        new_htlcs: Vec<Htlc>,
     ) -> Result<()> {
         let channel = &mut ctx.accounts.channel;
         
@@ -129,26 +319,57 @@ pub mod diap_payment_channel {
         let clock = Clock::get()?;
         require!(clock.unix_timestamp < channel.challenge_deadline, ErrorCode::ChallengePeriodExpired);
         
-        require!(
-            ctx.accounts.signer.key() == channel.participant1 || 
-            ctx.accounts.signer.key() == channel.participant2,
-            ErrorCode::NotChannelParticipant
-        );
+        // A registered watchtower may challenge on behalf of its participant
+        let signer = ctx.accounts.signer.key();
+        let (represented, is_watchtower) = if signer == channel.participant1 || signer == channel.participant2 {
+            (signer, false)
+        } else if channel.watchtower1 == Some(signer) {
+            (channel.participant1, true)
+        } else if channel.watchtower2 == Some(signer) {
+            (channel.participant2, true)
+        } else {
+            return Err(ErrorCode::NotChannelParticipant.into());
+        };
+        if is_watchtower {
+            require!(channel.close_initiator != represented, ErrorCode::WatchtowerCannotChallengeOwnClose);
+        }
         require!(new_nonce > channel.nonce, ErrorCode::NewNonceMustBeGreater);
-        
-        let total_new = new_balance1
-            .checked_add(new_balance2)
-            .ok_or(ErrorCode::MathOverflow)?;
-        require!(total_new <= channel.total_deposited, ErrorCode::InvalidBalanceDistribution);
 
-        // Note: Signature verification would happen here in a full implementation
+        // The submitted state must carry the counter-party's signature
+        let counterparty = counterparty_of(channel, represented);
+        let message = channel_state_message(
+            &channel.channel_id,
+            new_nonce,
+            new_balance1,
+            new_balance2,
+            new_deposited1,
+            new_deposited2,
+            &new_htlcs,
+        );
+        verify_ed25519_signatures(&ctx.accounts.instructions, &[counterparty], &message)?;
+
+        let locked = validate_htlcs(&new_htlcs)?;
+        let (new_balance1, new_balance2) =
+            settle_signed_balances(channel, new_balance1, new_balance2, new_deposited1, new_deposited2, locked)?;
 
         channel.balance1 = new_balance1;
         channel.balance2 = new_balance2;
+        channel.htlcs = new_htlcs;
         channel.nonce = new_nonce;
 
+        // The watchtower earns its bounty out of the close initiator's balance at finalization
+        if is_watchtower {
+            channel.bounty_recipient = Some(signer);
+            channel.bounty_bps = if represented == channel.participant1 {
+                channel.watchtower_bounty_bps1
+            } else {
+                channel.watchtower_bounty_bps2
+            };
+        }
+
         emit!(ChannelChallengedEvent {
             channel_id: channel.channel_id.clone(),
+            challenger: signer,
             new_nonce,
             new_balance1,
             new_balance2,
@@ -157,6 +378,55 @@ pub mod diap_payment_channel {
         Ok(())
     }
 
+    /// Resolve a pending HTLC during the dispute window: redeem it to the receiver
+    /// by revealing the keccak preimage before expiry, or refund it to the sender after expiry.
+    pub fn settle_htlc(
+        ctx: Context<SettleHtlc>,
+        index: u8,
+        preimage: Option<[u8; 32]>,
+    ) -> Result<()> {
+        let channel = &mut ctx.accounts.channel;
+
+        require!(channel.is_initialized, ErrorCode::ChannelNotFound);
+        require!(channel.is_active, ErrorCode::ChannelNotActive);
+        require!(channel.challenge_deadline > 0, ErrorCode::NoActiveChallengePeriod);
+
+        let clock = Clock::get()?;
+        require!(clock.unix_timestamp < channel.challenge_deadline, ErrorCode::ChallengePeriodExpired);
+        require!((index as usize) < channel.htlcs.len(), ErrorCode::HtlcNotFound);
+
+        let htlc = channel.htlcs[index as usize].clone();
+        let redeemed = match preimage {
+            Some(preimage) => {
+                require!(clock.unix_timestamp < htlc.expiry, ErrorCode::HtlcExpired);
+                require!(keccak::hash(&preimage).to_bytes() == htlc.hashlock, ErrorCode::InvalidPreimage);
+                true
+            }
+            None => {
+                require!(clock.unix_timestamp >= htlc.expiry, ErrorCode::HtlcNotExpired);
+                false
+            }
+        };
+
+        // Redeemed HTLCs go to the receiver, refunded ones back to the sender
+        let pays_participant1 = (htlc.direction == HtlcDirection::TwoToOne as u8) == redeemed;
+        if pays_participant1 {
+            channel.balance1 = channel.balance1.checked_add(htlc.amount).ok_or(ErrorCode::MathOverflow)?;
+        } else {
+            channel.balance2 = channel.balance2.checked_add(htlc.amount).ok_or(ErrorCode::MathOverflow)?;
+        }
+        channel.htlcs.swap_remove(index as usize);
+
+        emit!(HtlcSettledEvent {
+            channel_id: channel.channel_id.clone(),
+            hashlock: htlc.hashlock,
+            amount: htlc.amount,
+            redeemed,
+        });
+
+        Ok(())
+    }
+
     pub fn finalize_channel_close(ctx: Context<FinalizeChannelClose>) -> Result<()> {
         let channel = &mut ctx.accounts.channel;
         
@@ -167,22 +437,57 @@ pub mod diap_payment_channel {
         let clock = Clock::get()?;
         require!(clock.unix_timestamp >= channel.challenge_deadline, ErrorCode::ChallengePeriodNotEnded);
 
-        // Calculate fee
-        let fee = channel.total_deposited
-            .checked_mul(ctx.accounts.payment_channel.channel_fee_rate as u64)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(10000)
-            .ok_or(ErrorCode::MathDivision)?;
-        
+        // HTLCs not redeemed during the dispute window fall back to their sender
+        for htlc in std::mem::take(&mut channel.htlcs) {
+            if htlc.direction == HtlcDirection::OneToTwo as u8 {
+                channel.balance1 = channel.balance1.checked_add(htlc.amount).ok_or(ErrorCode::MathOverflow)?;
+            } else {
+                channel.balance2 = channel.balance2.checked_add(htlc.amount).ok_or(ErrorCode::MathOverflow)?;
+            }
+        }
+
+        // Pay the watchtower bounty out of the close initiator's balance
+        let mut bounty = 0;
+        if let Some(recipient) = channel.bounty_recipient {
+            let watchtower_token_account = ctx.accounts.watchtower_token_account
+                .as_ref()
+                .ok_or(ErrorCode::MissingWatchtowerTokenAccount)?;
+            require!(
+                watchtower_token_account.owner == recipient
+                    && watchtower_token_account.mint == ctx.accounts.payment_channel.token_mint,
+                ErrorCode::MissingWatchtowerTokenAccount
+            );
+
+            if channel.close_initiator == channel.participant1 {
+                bounty = channel_fee(channel.balance1, channel.bounty_bps)?;
+                channel.balance1 -= bounty;
+            } else {
+                bounty = channel_fee(channel.balance2, channel.bounty_bps)?;
+                channel.balance2 -= bounty;
+            }
+        }
+
         let total_to_distribute = channel.balance1
             .checked_add(channel.balance2)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_add(bounty)
             .ok_or(ErrorCode::MathOverflow)?;
-        
-        require!(total_to_distribute + fee <= channel.total_deposited, ErrorCode::InsufficientFunds);
+        require!(total_to_distribute == channel.total_deposited, ErrorCode::InvalidBalanceDistribution);
+
+        // Each side pays the fee on its own balance, so rounding dust stays with its owner
+        let fee_rate = ctx.accounts.payment_channel.channel_fee_rate;
+        let fee1 = channel_fee(channel.balance1, fee_rate)?;
+        let fee2 = channel_fee(channel.balance2, fee_rate)?;
+        let fee = fee1.checked_add(fee2).ok_or(ErrorCode::MathOverflow)?;
+
+        // Tokens sent to the vault outside of deposits belong to neither participant
+        let unaccounted = ctx.accounts.channel_vault.amount
+            .checked_sub(channel.total_deposited)
+            .ok_or(ErrorCode::InsufficientFunds)?;
 
         // Get channel values before mutable borrow
-        let balance1 = channel.balance1;
-        let balance2 = channel.balance2;
+        let balance1 = channel.balance1 - fee1;
+        let balance2 = channel.balance2 - fee2;
         let channel_id = channel.channel_id.clone();
         let bump = channel.bump;
 
@@ -225,151 +530,1067 @@ pub mod diap_payment_channel {
             token::transfer(cpi_ctx, balance2)?;
         }
 
-        channel.is_active = false;
+        let seeds = &[
+            b"channel",
+            channel_id.as_bytes(),
+            &[bump]
+        ];
+        let signer_seeds = &[&seeds[..]];
 
-        Ok(())
-    }
+        if bounty > 0 {
+            if let Some(watchtower_token_account) = &ctx.accounts.watchtower_token_account {
+                let cpi_accounts = Transfer {
+                    from: ctx.accounts.channel_vault.to_account_info(),
+                    to: watchtower_token_account.to_account_info(),
+                    authority: channel.to_account_info(),
+                };
+                let cpi_program = ctx.accounts.token_program.to_account_info();
+                let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+                token::transfer(cpi_ctx, bounty)?;
+            }
+        }
 
-    pub fn set_channel_fee_rate(ctx: Context<UpdateChannelFeeRate>, rate: u16) -> Result<()> {
-        require!(rate <= 100, ErrorCode::RateTooHigh);
-        
-        let payment_channel = &mut ctx.accounts.payment_channel;
-        payment_channel.channel_fee_rate = rate;
+        // Route fee and unaccounted tokens to treasury
+        let to_treasury = fee.checked_add(unaccounted).ok_or(ErrorCode::MathOverflow)?;
+        if to_treasury > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.channel_vault.to_account_info(),
+                to: ctx.accounts.channel_treasury.to_account_info(),
+                authority: channel.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, to_treasury)?;
+        }
 
-        emit!(ChannelFeeRateUpdatedEvent {
-            new_rate: rate,
+        channel.is_active = false;
+
+        emit!(ChannelFinalizedEvent {
+            channel_id,
+            final_balance1: balance1,
+            final_balance2: balance2,
+            fee,
+            watchtower_bounty: bounty,
+            unaccounted,
         });
 
         Ok(())
     }
-}
 
-// ============ Accounts ============
+    /// Settle a channel immediately from a final state signed by both participants,
+    /// skipping the challenge period, and close the channel and vault.
+    pub fn cooperative_close(
+        ctx: Context<CooperativeClose>,
+        final_balance1: u64,
+        final_balance2: u64,
+        deposited1: u64,
+        deposited2: u64,
+        nonce: u64,
+    ) -> Result<()> {
+        let channel = &ctx.accounts.channel;
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + PaymentChannelProgram::LEN,
-        seeds = [b"payment-channel-program", token_mint.key().as_ref()],
-        bump
-    )]
-    pub payment_channel: Account<'info, PaymentChannelProgram>,
-    
-    pub token_mint: Account<'info, Mint>,
-    
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    
-    pub system_program: Program<'info, System>,
-}
+        require!(channel.is_initialized, ErrorCode::ChannelNotFound);
+        require!(channel.is_active, ErrorCode::ChannelNotActive);
+        require!(nonce > channel.nonce, ErrorCode::NonceMustBeGreater);
 
-#[derive(Accounts)]
-#[instruction(participant2: Pubkey, deposit: u64, channel_id: String)]
-pub struct OpenPaymentChannel<'info> {
-    #[account(
-        init,
-        payer = participant1,
-        space = 8 + PaymentChannel::LEN,
-        seeds = [b"channel", channel_id.as_bytes()],
-        bump
-    )]
-    pub channel: Account<'info, PaymentChannel>,
-    
-    #[account(
-        mut,
-        seeds = [b"payment-channel-program", token_mint.key().as_ref()],
-        bump = payment_channel.bump
-    )]
-    pub payment_channel: Account<'info, PaymentChannelProgram>,
-    
-    #[account(
-        init_if_needed,
-        payer = participant1,
-        token::mint = token_mint,
-        token::authority = participant1,
-        seeds = [b"token-account", participant1.key().as_ref(), token_mint.key().as_ref()],
-        bump
-    )]
-    pub participant1_token_account: Account<'info, TokenAccount>,
-    
-    #[account(
-        init_if_needed,
-        payer = participant1,
-        token::mint = token_mint,
-        token::authority = channel,
-        seeds = [b"channel-vault", channel_id.as_bytes()],
-        bump
-    )]
-    pub channel_vault: Account<'info, TokenAccount>,
-    
-    /// CHECK: Participant1 address
-    #[account(mut)]
-    pub participant1: Signer<'info>,
-    
-    pub token_mint: Account<'info, Mint>,
-    
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-}
+        // Both participants must have signed the final close state
+        let message = channel_close_message(
+            &channel.channel_id,
+            nonce,
+            final_balance1,
+            final_balance2,
+            deposited1,
+            deposited2,
+        );
+        verify_ed25519_signatures(
+            &ctx.accounts.instructions,
+            &[channel.participant1, channel.participant2],
+            &message,
+        )?;
 
-#[derive(Accounts)]
-#[instruction(final_balance1: u64, final_balance2: u64, nonce: u64)]
-pub struct InitiateChannelClose<'info> {
-    #[account(
-        mut,
-        seeds = [b"channel", channel.channel_id.as_bytes()],
-        bump = channel.bump
-    )]
-    pub channel: Account<'info, PaymentChannel>,
-    
-    #[account(mut)]
-    pub signer: Signer<'info>,
-}
+        let (balance1, balance2) =
+            settle_signed_balances(channel, final_balance1, final_balance2, deposited1, deposited2, 0)?;
 
-#[derive(Accounts)]
-#[instruction(new_balance1: u64, new_balance2: u64, new_nonce: u64)]
-pub struct ChallengeChannelClose<'info> {
-    #[account(
-        mut,
-        seeds = [b"channel", channel.channel_id.as_bytes()],
-        bump = channel.bump
-    )]
-    pub channel: Account<'info, PaymentChannel>,
-    
-    #[account(mut)]
-    pub signer: Signer<'info>,
-}
+        // Each side pays the fee on its own balance, so rounding dust stays with its owner
+        let fee_rate = ctx.accounts.payment_channel.channel_fee_rate;
+        let fee1 = channel_fee(balance1, fee_rate)?;
+        let fee2 = channel_fee(balance2, fee_rate)?;
+        let fee = fee1.checked_add(fee2).ok_or(ErrorCode::MathOverflow)?;
+        let final_balance1 = balance1 - fee1;
+        let final_balance2 = balance2 - fee2;
 
-#[derive(Accounts)]
-pub struct FinalizeChannelClose<'info> {
-    #[account(
-        mut,
-        seeds = [b"channel", channel.channel_id.as_bytes()],
-        bump = channel.bump
-    )]
-    pub channel: Account<'info, PaymentChannel>,
-    
-    #[account(
+        // The vault is closed below, so tokens sent to it outside of deposits go to the treasury
+        let unaccounted = ctx.accounts.channel_vault.amount
+            .checked_sub(channel.total_deposited)
+            .ok_or(ErrorCode::InsufficientFunds)?;
+
+        let channel_id = channel.channel_id.clone();
+        let seeds = &[
+            b"channel",
+            channel_id.as_bytes(),
+            &[channel.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        if final_balance1 > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.channel_vault.to_account_info(),
+                to: ctx.accounts.participant1_token_account.to_account_info(),
+                authority: channel.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, final_balance1)?;
+        }
+
+        if final_balance2 > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.channel_vault.to_account_info(),
+                to: ctx.accounts.participant2_token_account.to_account_info(),
+                authority: channel.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, final_balance2)?;
+        }
+
+        let to_treasury = fee.checked_add(unaccounted).ok_or(ErrorCode::MathOverflow)?;
+        if to_treasury > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.channel_vault.to_account_info(),
+                to: ctx.accounts.channel_treasury.to_account_info(),
+                authority: channel.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, to_treasury)?;
+        }
+
+        // Close the emptied vault; the channel account itself is closed by Anchor
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.channel_vault.to_account_info(),
+            destination: ctx.accounts.participant1.to_account_info(),
+            authority: channel.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::close_account(cpi_ctx)?;
+
+        emit!(ChannelCooperativelyClosedEvent {
+            channel_id,
+            nonce,
+            final_balance1,
+            final_balance2,
+            fee,
+        });
+
+        Ok(())
+    }
+
+    /// Open a multi-party hub channel funded by the opener. Hub states always
+    /// distribute exactly the deposited amount across all participants.
+    pub fn open_hub_channel(
+        ctx: Context<OpenHubChannel>,
+        hub_id: String,
+        participants: Vec<Pubkey>,
+        deposit: u64,
+        dispute_period: i64,
+    ) -> Result<()> {
+        require!(deposit > 0, ErrorCode::DepositMustBeGreaterThanZero);
+        require!(!hub_id.is_empty(), ErrorCode::ChannelIDRequired);
+        require!(
+            participants.len() >= 2 && participants.len() <= MAX_HUB_PARTICIPANTS,
+            ErrorCode::InvalidHubParticipants
+        );
+        for (i, participant) in participants.iter().enumerate() {
+            require!(!participants[..i].contains(participant), ErrorCode::InvalidHubParticipants);
+        }
+        require!(
+            dispute_period >= ctx.accounts.payment_channel.min_dispute_period
+                && dispute_period <= ctx.accounts.payment_channel.max_dispute_period,
+            ErrorCode::InvalidDisputePeriod
+        );
+
+        let opener = ctx.accounts.opener.key();
+        let opener_index = participants
+            .iter()
+            .position(|p| *p == opener)
+            .ok_or(ErrorCode::NotChannelParticipant)?;
+
+        let mut balances = vec![0u64; participants.len()];
+        balances[opener_index] = deposit;
+
+        let hub = &mut ctx.accounts.hub;
+        hub.hub_id = hub_id.clone();
+        hub.withdrawn = vec![false; participants.len()];
+        hub.participants = participants.clone();
+        hub.balances = balances;
+        hub.total_deposited = deposit;
+        hub.nonce = 0;
+        hub.is_active = true;
+        hub.last_update = Clock::get()?.unix_timestamp;
+        hub.challenge_deadline = 0;
+        hub.dispute_period = dispute_period;
+        hub.bump = ctx.bumps.hub;
+        hub.is_initialized = true;
+        hub.opener = opener;
+
+        // Transfer deposit from opener to hub vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.opener_token_account.to_account_info(),
+            to: ctx.accounts.hub_vault.to_account_info(),
+            authority: ctx.accounts.opener.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, deposit)?;
+
+        emit!(HubChannelOpenedEvent {
+            hub_id,
+            opener,
+            participants,
+            total_deposit: deposit,
+        });
+
+        Ok(())
+    }
+
+    /// Record participant signatures over a proposed hub state. A single Ed25519
+    /// instruction only fits a handful of signatures, so larger hubs collect them
+    /// over several transactions; any caller may relay them.
+    pub fn sign_hub_state(
+        ctx: Context<SignHubState>,
+        nonce: u64,
+        balances: Vec<u64>,
+    ) -> Result<()> {
+        let hub = &ctx.accounts.hub;
+
+        require!(hub.is_initialized, ErrorCode::ChannelNotFound);
+        require!(hub.is_active, ErrorCode::ChannelNotActive);
+        require!(nonce > hub.nonce, ErrorCode::NonceMustBeGreater);
+        validate_hub_balances(hub, &balances)?;
+
+        let message = hub_state_message(&hub.hub_id, nonce, &balances, hub.total_deposited);
+        let signatures = load_state_signatures(&ctx.accounts.instructions)?;
+
+        let hub_state = &mut ctx.accounts.hub_state;
+        if hub_state.hub == Pubkey::default() {
+            hub_state.hub = hub.key();
+            hub_state.message = message;
+            hub_state.signed = vec![false; hub.participants.len()];
+            hub_state.payer = ctx.accounts.payer.key();
+            hub_state.bump = ctx.bumps.hub_state;
+        }
+
+        let mut added = 0u32;
+        for (i, participant) in hub.participants.iter().enumerate() {
+            if !hub_state.signed[i]
+                && signatures.iter().any(|entry| entry.public_key == *participant && entry.message == message)
+            {
+                hub_state.signed[i] = true;
+                added += 1;
+            }
+        }
+        require!(added > 0, ErrorCode::MissingStateSignature);
+
+        emit!(HubStateSignedEvent {
+            hub_id: hub.hub_id.clone(),
+            nonce,
+            signatures: hub_state.signed.iter().filter(|signed| **signed).count() as u32,
+        });
+
+        Ok(())
+    }
+
+    /// Submit a hub state signed by every participant and start the dispute window
+    pub fn initiate_hub_close(
+        ctx: Context<UpdateHubState>,
+        nonce: u64,
+        balances: Vec<u64>,
+    ) -> Result<()> {
+        let hub = &mut ctx.accounts.hub;
+
+        require!(hub.is_initialized, ErrorCode::ChannelNotFound);
+        require!(hub.is_active, ErrorCode::ChannelNotActive);
+        require!(hub.challenge_deadline == 0, ErrorCode::ChannelClosing);
+
+        apply_hub_state(hub, &ctx.accounts.hub_state, ctx.accounts.signer.key(), nonce, balances)?;
+        hub.challenge_deadline = Clock::get()?.unix_timestamp
+            .checked_add(hub.dispute_period)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(HubStateSubmittedEvent {
+            hub_id: hub.hub_id.clone(),
+            submitter: ctx.accounts.signer.key(),
+            nonce,
+            challenge_deadline: hub.challenge_deadline,
+        });
+
+        Ok(())
+    }
+
+    /// Start the dispute window on the hub state already recorded on-chain (the opening
+    /// deposit at nonce 0) when other participants stop responding
+    pub fn force_hub_close(ctx: Context<ForceHubClose>) -> Result<()> {
+        let hub = &mut ctx.accounts.hub;
+        let submitter = ctx.accounts.signer.key();
+
+        require!(hub.is_initialized, ErrorCode::ChannelNotFound);
+        require!(hub.is_active, ErrorCode::ChannelNotActive);
+        require!(hub.challenge_deadline == 0, ErrorCode::ChannelClosing);
+        require!(hub.participants.contains(&submitter), ErrorCode::NotChannelParticipant);
+
+        hub.challenge_deadline = Clock::get()?.unix_timestamp
+            .checked_add(hub.dispute_period)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(HubStateSubmittedEvent {
+            hub_id: hub.hub_id.clone(),
+            submitter,
+            nonce: hub.nonce,
+            challenge_deadline: hub.challenge_deadline,
+        });
+
+        Ok(())
+    }
+
+    /// Replace the submitted hub state with a newer one during the dispute window
+    pub fn challenge_hub_close(
+        ctx: Context<UpdateHubState>,
+        nonce: u64,
+        balances: Vec<u64>,
+    ) -> Result<()> {
+        let hub = &mut ctx.accounts.hub;
+
+        require!(hub.is_initialized, ErrorCode::ChannelNotFound);
+        require!(hub.is_active, ErrorCode::ChannelNotActive);
+        require!(hub.challenge_deadline > 0, ErrorCode::NoActiveChallengePeriod);
+        require!(
+            Clock::get()?.unix_timestamp < hub.challenge_deadline,
+            ErrorCode::ChallengePeriodExpired
+        );
+
+        apply_hub_state(hub, &ctx.accounts.hub_state, ctx.accounts.signer.key(), nonce, balances)?;
+
+        emit!(HubStateSubmittedEvent {
+            hub_id: hub.hub_id.clone(),
+            submitter: ctx.accounts.signer.key(),
+            nonce,
+            challenge_deadline: hub.challenge_deadline,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw the caller's final hub balance once the dispute window has ended
+    pub fn withdraw_from_hub(ctx: Context<WithdrawFromHub>) -> Result<()> {
+        let hub = &mut ctx.accounts.hub;
+        let participant = ctx.accounts.participant.key();
+
+        require!(hub.is_initialized, ErrorCode::ChannelNotFound);
+        require!(hub.challenge_deadline > 0, ErrorCode::NoActiveChallengePeriod);
+        require!(
+            Clock::get()?.unix_timestamp >= hub.challenge_deadline,
+            ErrorCode::ChallengePeriodNotEnded
+        );
+
+        let index = hub
+            .participants
+            .iter()
+            .position(|p| *p == participant)
+            .ok_or(ErrorCode::NotChannelParticipant)?;
+        require!(!hub.withdrawn[index], ErrorCode::AlreadyWithdrawn);
+
+        let balance = hub.balances[index];
+        let fee = channel_fee(balance, ctx.accounts.payment_channel.channel_fee_rate)?;
+        let payout = balance.checked_sub(fee).ok_or(ErrorCode::InsufficientFunds)?;
+
+        hub.withdrawn[index] = true;
+        hub.is_active = false;
+
+        // The last withdrawal empties the vault, so tokens sent to it outside of deposits go to the treasury
+        let is_last = hub.withdrawn.iter().all(|withdrawn| *withdrawn);
+        let unaccounted = if is_last {
+            ctx.accounts.hub_vault.amount
+                .checked_sub(balance)
+                .ok_or(ErrorCode::InsufficientFunds)?
+        } else {
+            0
+        };
+        let to_treasury = fee.checked_add(unaccounted).ok_or(ErrorCode::MathOverflow)?;
+
+        let hub_id = hub.hub_id.clone();
+        let seeds = &[
+            b"hub",
+            hub_id.as_bytes(),
+            &[hub.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        if payout > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.hub_vault.to_account_info(),
+                to: ctx.accounts.participant_token_account.to_account_info(),
+                authority: hub.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, payout)?;
+        }
+
+        // Route fee to treasury
+        if to_treasury > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.hub_vault.to_account_info(),
+                to: ctx.accounts.channel_treasury.to_account_info(),
+                authority: hub.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, to_treasury)?;
+        }
+
+        // Close the emptied vault and the hub once everyone has withdrawn
+        if is_last {
+            let cpi_accounts = CloseAccount {
+                account: ctx.accounts.hub_vault.to_account_info(),
+                destination: ctx.accounts.opener.to_account_info(),
+                authority: hub.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::close_account(cpi_ctx)?;
+        }
+
+        emit!(HubWithdrawalEvent {
+            hub_id,
+            participant,
+            amount: payout,
+            fee,
+        });
+
+        if is_last {
+            ctx.accounts.hub.close(ctx.accounts.opener.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    /// Withdraw collected channel fees from the treasury (authority only)
+    pub fn withdraw_channel_fees(ctx: Context<WithdrawChannelFees>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(ctx.accounts.channel_treasury.amount >= amount, ErrorCode::InsufficientFunds);
+
+        let payment_channel = &ctx.accounts.payment_channel;
+        let seeds = &[
+            b"payment-channel-program",
+            payment_channel.token_mint.as_ref(),
+            &[payment_channel.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.channel_treasury.to_account_info(),
+            to: ctx.accounts.destination_token_account.to_account_info(),
+            authority: payment_channel.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, amount)?;
+
+        emit!(ChannelFeesWithdrawnEvent {
+            to: ctx.accounts.destination_token_account.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_dispute_period_bounds(
+        ctx: Context<UpdateChannelConfig>,
+        min_dispute_period: i64,
+        max_dispute_period: i64,
+    ) -> Result<()> {
+        require!(
+            min_dispute_period > 0 && min_dispute_period <= max_dispute_period,
+            ErrorCode::InvalidDisputePeriodBounds
+        );
+
+        let payment_channel = &mut ctx.accounts.payment_channel;
+        payment_channel.min_dispute_period = min_dispute_period;
+        payment_channel.max_dispute_period = max_dispute_period;
+
+        emit!(DisputePeriodBoundsUpdatedEvent {
+            min_dispute_period,
+            max_dispute_period,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_channel_fee_rate(ctx: Context<UpdateChannelConfig>, rate: u16) -> Result<()> {
+        require!(rate <= 100, ErrorCode::RateTooHigh);
+        
+        let payment_channel = &mut ctx.accounts.payment_channel;
+        payment_channel.channel_fee_rate = rate;
+
+        emit!(ChannelFeeRateUpdatedEvent {
+            new_rate: rate,
+        });
+
+        Ok(())
+    }
+
+    /// Create the fee treasury for a deployment initialized before channel fees
+    /// were collected on-chain (authority only)
+    pub fn init_channel_treasury(_ctx: Context<InitChannelTreasury>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Grow a `PaymentChannelProgram` created before the dispute period bounds
+    /// were added and set them (authority only)
+    pub fn migrate_channel_program(
+        ctx: Context<MigrateChannelProgram>,
+        min_dispute_period: i64,
+        max_dispute_period: i64,
+    ) -> Result<()> {
+        require!(
+            min_dispute_period > 0 && min_dispute_period <= max_dispute_period,
+            ErrorCode::InvalidDisputePeriodBounds
+        );
+
+        let info = ctx.accounts.payment_channel.to_account_info();
+        require!(info.data_len() < 8 + PaymentChannelProgram::LEN, ErrorCode::AlreadyMigrated);
+        {
+            // `authority` is the first field in every layout
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 + 32 && data[..8] == PaymentChannelProgram::DISCRIMINATOR,
+                ErrorCode::InvalidLegacyAccount
+            );
+            require!(data[8..40] == ctx.accounts.authority.key().to_bytes(), ErrorCode::Unauthorized);
+        }
+
+        grow_account(
+            &info,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.system_program,
+            8 + PaymentChannelProgram::LEN,
+        )?;
+
+        let mut data = info.try_borrow_mut_data()?;
+        let mut payment_channel = PaymentChannelProgram::try_deserialize(&mut &data[..])?;
+        payment_channel.min_dispute_period = min_dispute_period;
+        payment_channel.max_dispute_period = max_dispute_period;
+        let mut writer: &mut [u8] = &mut data;
+        payment_channel.try_serialize(&mut writer)?;
+
+        Ok(())
+    }
+
+    /// Rewrite a `PaymentChannel` opened before the upgrade into the current layout.
+    /// Permissionless; the payer covers the larger account's rent. Legacy closes
+    /// were never signed, so a close in progress is discarded and the channel
+    /// restarts from its deposits, all credited to participant1 who made them.
+    pub fn migrate_channel(ctx: Context<MigrateChannel>, _channel_id: String) -> Result<()> {
+        let info = ctx.accounts.channel.to_account_info();
+        require!(info.data_len() < 8 + PaymentChannel::LEN, ErrorCode::AlreadyMigrated);
+
+        let legacy = {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == PaymentChannel::DISCRIMINATOR,
+                ErrorCode::InvalidLegacyAccount
+            );
+            LegacyPaymentChannel::deserialize(&mut &data[8..])
+                .map_err(|_| error!(ErrorCode::InvalidLegacyAccount))?
+        };
+
+        grow_account(
+            &info,
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.system_program,
+            8 + PaymentChannel::LEN,
+        )?;
+
+        let channel = PaymentChannel {
+            participant1: legacy.participant1,
+            participant2: legacy.participant2,
+            balance1: legacy.total_deposited,
+            balance2: 0,
+            total_deposited: legacy.total_deposited,
+            deposited1: legacy.total_deposited,
+            deposited2: 0,
+            nonce: legacy.nonce,
+            is_active: legacy.is_active,
+            last_update: legacy.last_update,
+            challenge_deadline: 0,
+            dispute_period: LEGACY_DISPUTE_PERIOD,
+            htlcs: Vec::new(),
+            close_initiator: Pubkey::default(),
+            watchtower1: None,
+            watchtower2: None,
+            watchtower_bounty_bps1: 0,
+            watchtower_bounty_bps2: 0,
+            bounty_recipient: None,
+            bounty_bps: 0,
+            channel_id: legacy.channel_id,
+            bump: legacy.bump,
+            is_initialized: legacy.is_initialized,
+        };
+        let mut data = info.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data;
+        channel.try_serialize(&mut writer)?;
+
+        Ok(())
+    }
+}
+
+// ============ Accounts ============
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PaymentChannelProgram::LEN,
+        seeds = [b"payment-channel-program", token_mint.key().as_ref()],
+        bump
+    )]
+    pub payment_channel: Account<'info, PaymentChannelProgram>,
+    
+    #[account(
+        init,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = payment_channel,
+        seeds = [b"channel-treasury", token_mint.key().as_ref()],
+        bump
+    )]
+    pub channel_treasury: Account<'info, TokenAccount>,
+    
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(participant2: Pubkey, deposit: u64, channel_id: String, dispute_period: i64)]
+pub struct OpenPaymentChannel<'info> {
+    #[account(
+        init,
+        payer = participant1,
+        space = 8 + PaymentChannel::LEN,
+        seeds = [b"channel", channel_id.as_bytes()],
+        bump
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    
+    #[account(
+        mut,
+        seeds = [b"payment-channel-program", token_mint.key().as_ref()],
+        bump = payment_channel.bump
+    )]
+    pub payment_channel: Account<'info, PaymentChannelProgram>,
+    
+    #[account(
+        init_if_needed,
+        payer = participant1,
+        token::mint = token_mint,
+        token::authority = participant1,
+        seeds = [b"token-account", participant1.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub participant1_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        init_if_needed,
+        payer = participant1,
+        token::mint = token_mint,
+        token::authority = channel,
+        seeds = [b"channel-vault", channel_id.as_bytes()],
+        bump
+    )]
+    pub channel_vault: Account<'info, TokenAccount>,
+    
+    /// CHECK: Participant1 address
+    #[account(mut)]
+    pub participant1: Signer<'info>,
+    
+    pub token_mint: Account<'info, Mint>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositToChannel<'info> {
+    #[account(
+        mut,
+        seeds = [b"channel", channel.channel_id.as_bytes()],
+        bump = channel.bump
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    
+    #[account(
+        mut,
+        constraint = channel_vault.key() == get_channel_vault_pda(&channel.channel_id)
+    )]
+    pub channel_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = payment_channel.token_mint,
+        token::authority = depositor
+    )]
+    pub depositor_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"payment-channel-program", payment_channel.token_mint.as_ref()],
+        bump = payment_channel.bump
+    )]
+    pub payment_channel: Account<'info, PaymentChannelProgram>,
+    
+    pub depositor: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterWatchtower<'info> {
+    #[account(
+        mut,
+        seeds = [b"channel", channel.channel_id.as_bytes()],
+        bump = channel.bump
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    
+    pub participant: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetWatchtowerBounty<'info> {
+    #[account(
+        mut,
+        seeds = [b"channel", channel.channel_id.as_bytes()],
+        bump = channel.bump
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    
+    #[account(address = channel.participant1)]
+    pub participant1: Signer<'info>,
+    
+    #[account(address = channel.participant2)]
+    pub participant2: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(final_balance1: u64, final_balance2: u64, deposited1: u64, deposited2: u64, nonce: u64)]
+pub struct InitiateChannelClose<'info> {
+    #[account(
+        mut,
+        seeds = [b"channel", channel.channel_id.as_bytes()],
+        bump = channel.bump
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    
+    /// CHECK: Instructions sysvar, checked by address
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ForceCloseChannel<'info> {
+    #[account(
+        mut,
+        seeds = [b"channel", channel.channel_id.as_bytes()],
+        bump = channel.bump
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SettleHtlc<'info> {
+    #[account(
+        mut,
+        seeds = [b"channel", channel.channel_id.as_bytes()],
+        bump = channel.bump
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+}
+
+#[derive(Accounts)]
+#[instruction(new_balance1: u64, new_balance2: u64, new_deposited1: u64, new_deposited2: u64, new_nonce: u64)]
+pub struct ChallengeChannelClose<'info> {
+    #[account(
+        mut,
+        seeds = [b"channel", channel.channel_id.as_bytes()],
+        bump = channel.bump
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    
+    /// CHECK: Instructions sysvar, checked by address
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeChannelClose<'info> {
+    #[account(
+        mut,
+        seeds = [b"channel", channel.channel_id.as_bytes()],
+        bump = channel.bump
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    
+    #[account(
+        mut,
+        constraint = channel_vault.key() == get_channel_vault_pda(&channel.channel_id)
+    )]
+    pub channel_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = payment_channel.token_mint,
+        token::authority = channel.participant1
+    )]
+    pub participant1_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = payment_channel.token_mint,
+        token::authority = channel.participant2
+    )]
+    pub participant2_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = channel_treasury.key() == get_channel_treasury_pda(&payment_channel.token_mint)
+    )]
+    pub channel_treasury: Account<'info, TokenAccount>,
+    
+    /// Required when a watchtower bounty is owed
+    #[account(mut)]
+    pub watchtower_token_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        seeds = [b"payment-channel-program", payment_channel.token_mint.as_ref()],
+        bump = payment_channel.bump
+    )]
+    pub payment_channel: Account<'info, PaymentChannelProgram>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CooperativeClose<'info> {
+    #[account(
+        mut,
+        seeds = [b"channel", channel.channel_id.as_bytes()],
+        bump = channel.bump,
+        close = participant1
+    )]
+    pub channel: Account<'info, PaymentChannel>,
+    
+    #[account(
+        mut,
+        constraint = channel_vault.key() == get_channel_vault_pda(&channel.channel_id)
+    )]
+    pub channel_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = payment_channel.token_mint,
+        token::authority = channel.participant1
+    )]
+    pub participant1_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = payment_channel.token_mint,
+        token::authority = channel.participant2
+    )]
+    pub participant2_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = channel_treasury.key() == get_channel_treasury_pda(&payment_channel.token_mint)
+    )]
+    pub channel_treasury: Account<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"payment-channel-program", payment_channel.token_mint.as_ref()],
+        bump = payment_channel.bump
+    )]
+    pub payment_channel: Account<'info, PaymentChannelProgram>,
+    
+    /// CHECK: Participant1, paid the channel and vault rent and receives it back
+    #[account(mut, address = channel.participant1)]
+    pub participant1: UncheckedAccount<'info>,
+    
+    /// CHECK: Instructions sysvar, checked by address
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(hub_id: String, participants: Vec<Pubkey>)]
+pub struct OpenHubChannel<'info> {
+    #[account(
+        init,
+        payer = opener,
+        space = 8 + HubChannel::space(participants.len()),
+        seeds = [b"hub", hub_id.as_bytes()],
+        bump
+    )]
+    pub hub: Account<'info, HubChannel>,
+    
+    #[account(
+        init,
+        payer = opener,
+        token::mint = token_mint,
+        token::authority = hub,
+        seeds = [b"hub-vault", hub_id.as_bytes()],
+        bump
+    )]
+    pub hub_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"payment-channel-program", token_mint.key().as_ref()],
+        bump = payment_channel.bump
+    )]
+    pub payment_channel: Account<'info, PaymentChannelProgram>,
+    
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = opener
+    )]
+    pub opener_token_account: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub opener: Signer<'info>,
+    
+    pub token_mint: Account<'info, Mint>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u64, balances: Vec<u64>)]
+pub struct SignHubState<'info> {
+    #[account(
+        seeds = [b"hub", hub.hub_id.as_bytes()],
+        bump = hub.bump
+    )]
+    pub hub: Account<'info, HubChannel>,
+    
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + HubStateSignatures::space(hub.participants.len()),
+        seeds = [
+            b"hub-state",
+            hub.key().as_ref(),
+            hub_state_message(&hub.hub_id, nonce, &balances, hub.total_deposited).as_ref()
+        ],
+        bump
+    )]
+    pub hub_state: Account<'info, HubStateSignatures>,
+    
+    /// CHECK: Instructions sysvar, checked by address
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u64, balances: Vec<u64>)]
+pub struct UpdateHubState<'info> {
+    #[account(
+        mut,
+        seeds = [b"hub", hub.hub_id.as_bytes()],
+        bump = hub.bump
+    )]
+    pub hub: Account<'info, HubChannel>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"hub-state",
+            hub.key().as_ref(),
+            hub_state_message(&hub.hub_id, nonce, &balances, hub.total_deposited).as_ref()
+        ],
+        bump = hub_state.bump,
+        close = hub_state_payer
+    )]
+    pub hub_state: Account<'info, HubStateSignatures>,
+    
+    /// CHECK: Paid for the collected signatures account and receives its rent back
+    #[account(mut, address = hub_state.payer)]
+    pub hub_state_payer: UncheckedAccount<'info>,
+    
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ForceHubClose<'info> {
+    #[account(
         mut,
-        constraint = channel_vault.key() == get_channel_vault_pda(&channel.channel_id)
+        seeds = [b"hub", hub.hub_id.as_bytes()],
+        bump = hub.bump
     )]
-    pub channel_vault: Account<'info, TokenAccount>,
+    pub hub: Account<'info, HubChannel>,
     
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFromHub<'info> {
     #[account(
         mut,
-        token::mint = payment_channel.token_mint,
-        token::authority = channel.participant1
+        seeds = [b"hub", hub.hub_id.as_bytes()],
+        bump = hub.bump
     )]
-    pub participant1_token_account: Account<'info, TokenAccount>,
+    pub hub: Account<'info, HubChannel>,
+    
+    #[account(
+        mut,
+        constraint = hub_vault.key() == get_hub_vault_pda(&hub.hub_id)
+    )]
+    pub hub_vault: Account<'info, TokenAccount>,
     
     #[account(
         mut,
         token::mint = payment_channel.token_mint,
-        token::authority = channel.participant2
+        token::authority = participant
     )]
-    pub participant2_token_account: Account<'info, TokenAccount>,
+    pub participant_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = channel_treasury.key() == get_channel_treasury_pda(&payment_channel.token_mint)
+    )]
+    pub channel_treasury: Account<'info, TokenAccount>,
     
     #[account(
         seeds = [b"payment-channel-program", payment_channel.token_mint.as_ref()],
@@ -377,21 +1598,118 @@ pub struct FinalizeChannelClose<'info> {
     )]
     pub payment_channel: Account<'info, PaymentChannelProgram>,
     
+    /// CHECK: Hub opener, receives the hub and vault rent after the last withdrawal
+    #[account(mut, address = hub.opener)]
+    pub opener: UncheckedAccount<'info>,
+    
+    pub participant: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawChannelFees<'info> {
+    #[account(
+        seeds = [b"payment-channel-program", payment_channel.token_mint.as_ref()],
+        bump = payment_channel.bump,
+        has_one = authority
+    )]
+    pub payment_channel: Account<'info, PaymentChannelProgram>,
+    
+    #[account(
+        mut,
+        constraint = channel_treasury.key() == get_channel_treasury_pda(&payment_channel.token_mint)
+    )]
+    pub channel_treasury: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = payment_channel.token_mint
+    )]
+    pub destination_token_account: Account<'info, TokenAccount>,
+    
+    pub authority: Signer<'info>,
+    
     pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-pub struct UpdateChannelFeeRate<'info> {
+pub struct UpdateChannelConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"payment-channel-program", token_mint.key().as_ref()],
+        bump = payment_channel.bump,
+        has_one = authority
+    )]
+    pub payment_channel: Account<'info, PaymentChannelProgram>,
+    
+    pub token_mint: Account<'info, Mint>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateChannelProgram<'info> {
+    /// CHECK: Pre-upgrade `PaymentChannelProgram`, too short to deserialize as the current layout
+    #[account(
+        mut,
+        seeds = [b"payment-channel-program", token_mint.key().as_ref()],
+        bump,
+        owner = crate::ID
+    )]
+    pub payment_channel: UncheckedAccount<'info>,
+    
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(channel_id: String)]
+pub struct MigrateChannel<'info> {
+    /// CHECK: Pre-upgrade `PaymentChannel`, decoded by hand since it does not match the current layout
     #[account(
         mut,
+        seeds = [b"channel", channel_id.as_bytes()],
+        bump,
+        owner = crate::ID
+    )]
+    pub channel: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitChannelTreasury<'info> {
+    #[account(
         seeds = [b"payment-channel-program", token_mint.key().as_ref()],
         bump = payment_channel.bump,
         has_one = authority
     )]
     pub payment_channel: Account<'info, PaymentChannelProgram>,
     
+    #[account(
+        init,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = payment_channel,
+        seeds = [b"channel-treasury", token_mint.key().as_ref()],
+        bump
+    )]
+    pub channel_treasury: Account<'info, TokenAccount>,
+    
     pub token_mint: Account<'info, Mint>,
+    
+    #[account(mut)]
     pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
 // ============ State ============
@@ -402,10 +1720,12 @@ pub struct PaymentChannelProgram {
     pub token_mint: Pubkey,
     pub channel_fee_rate: u16,
     pub bump: u8,
+    pub min_dispute_period: i64,
+    pub max_dispute_period: i64,
 }
 
 impl PaymentChannelProgram {
-    pub const LEN: usize = 32 + 32 + 2 + 1;
+    pub const LEN: usize = 32 + 32 + 2 + 1 + 8 + 8;
 }
 
 #[account]
@@ -415,17 +1735,109 @@ pub struct PaymentChannel {
     pub balance1: u64,
     pub balance2: u64,
     pub total_deposited: u64,
+    pub deposited1: u64,
+    pub deposited2: u64,
     pub nonce: u64,
     pub is_active: bool,
     pub last_update: i64,
     pub challenge_deadline: i64,
+    pub dispute_period: i64,
+    pub htlcs: Vec<Htlc>,
+    pub close_initiator: Pubkey,
+    pub watchtower1: Option<Pubkey>,
+    pub watchtower2: Option<Pubkey>,
+    pub watchtower_bounty_bps1: u16,
+    pub watchtower_bounty_bps2: u16,
+    pub bounty_recipient: Option<Pubkey>,
+    pub bounty_bps: u16,
     pub channel_id: String,
     pub bump: u8,
     pub is_initialized: bool,
 }
 
 impl PaymentChannel {
-    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8 + 1 + 8 + 8 + 100 + 1 + 1;
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 1 + 8 + 8 + 8 + (4 + MAX_HTLCS * Htlc::LEN)
+        + 32 + (1 + 32) + (1 + 32) + 2 + 2 + (1 + 32) + 2 + 100 + 1 + 1;
+}
+
+/// `PaymentChannel` layout before signed states, top-ups and disputes were
+/// added, read only by `migrate_channel`
+#[derive(AnchorDeserialize)]
+pub struct LegacyPaymentChannel {
+    pub participant1: Pubkey,
+    pub participant2: Pubkey,
+    pub balance1: u64,
+    pub balance2: u64,
+    pub total_deposited: u64,
+    pub nonce: u64,
+    pub is_active: bool,
+    pub last_update: i64,
+    pub challenge_deadline: i64,
+    pub channel_id: String,
+    pub bump: u8,
+    pub is_initialized: bool,
+}
+
+/// Fixed dispute window of channels opened before it was configurable
+const LEGACY_DISPUTE_PERIOD: i64 = 24 * 60 * 60;
+
+const MAX_HTLCS: usize = 8;
+
+/// Upper bound on a watchtower bounty, in basis points of the cheating side's balance
+const MAX_WATCHTOWER_BOUNTY_BPS: u16 = 1000;
+
+/// Hash-time-locked conditional payment carried in a signed channel state
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct Htlc {
+    pub amount: u64,
+    pub hashlock: [u8; 32],
+    pub expiry: i64,
+    pub direction: u8,
+}
+
+impl Htlc {
+    pub const LEN: usize = 8 + 32 + 8 + 1;
+}
+
+const MAX_HUB_PARTICIPANTS: usize = 32;
+
+#[account]
+pub struct HubChannel {
+    pub participants: Vec<Pubkey>,
+    pub balances: Vec<u64>,
+    pub withdrawn: Vec<bool>,
+    pub total_deposited: u64,
+    pub nonce: u64,
+    pub is_active: bool,
+    pub last_update: i64,
+    pub challenge_deadline: i64,
+    pub dispute_period: i64,
+    pub hub_id: String,
+    pub bump: u8,
+    pub is_initialized: bool,
+    pub opener: Pubkey, // Paid the hub and vault rent and receives it back
+}
+
+impl HubChannel {
+    pub fn space(participants: usize) -> usize {
+        (4 + participants * 32) + (4 + participants * 8) + (4 + participants) + 8 + 8 + 1 + 8 + 8 + 8 + 100 + 1 + 1 + 32
+    }
+}
+
+/// Participant signatures collected so far for one proposed hub state, keyed by its message
+#[account]
+pub struct HubStateSignatures {
+    pub hub: Pubkey,
+    pub message: [u8; 32],
+    pub signed: Vec<bool>,
+    pub payer: Pubkey,
+    pub bump: u8,
+}
+
+impl HubStateSignatures {
+    pub fn space(participants: usize) -> usize {
+        32 + 32 + (4 + participants) + 32 + 1
+    }
 }
 
 // ============ Events ============
@@ -439,6 +1851,32 @@ pub struct PaymentChannelOpenedEvent {
     pub total_deposit: u64,
 }
 
+#[event]
+pub struct ChannelDepositEvent {
+    #[index]
+    pub channel_id: String,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    pub total_deposited: u64,
+}
+
+#[event]
+pub struct WatchtowerRegisteredEvent {
+    #[index]
+    pub channel_id: String,
+    pub participant: Pubkey,
+    pub watchtower: Option<Pubkey>,
+    pub bounty_bps: u16,
+}
+
+#[event]
+pub struct WatchtowerBountySetEvent {
+    #[index]
+    pub channel_id: String,
+    pub participant: Pubkey,
+    pub bounty_bps: u16,
+}
+
 #[event]
 pub struct PaymentChannelClosedEvent {
     #[index]
@@ -447,15 +1885,93 @@ pub struct PaymentChannelClosedEvent {
     pub final_balance2: u64,
 }
 
+#[event]
+pub struct HtlcSettledEvent {
+    #[index]
+    pub channel_id: String,
+    pub hashlock: [u8; 32],
+    pub amount: u64,
+    pub redeemed: bool,
+}
+
+#[event]
+pub struct ChannelFinalizedEvent {
+    #[index]
+    pub channel_id: String,
+    pub final_balance1: u64,
+    pub final_balance2: u64,
+    pub fee: u64,
+    pub watchtower_bounty: u64,
+    pub unaccounted: u64,
+}
+
 #[event]
 pub struct ChannelChallengedEvent {
     #[index]
     pub channel_id: String,
+    pub challenger: Pubkey,
     pub new_nonce: u64,
     pub new_balance1: u64,
     pub new_balance2: u64,
 }
 
+#[event]
+pub struct ChannelCooperativelyClosedEvent {
+    #[index]
+    pub channel_id: String,
+    pub nonce: u64,
+    pub final_balance1: u64,
+    pub final_balance2: u64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct HubChannelOpenedEvent {
+    #[index]
+    pub hub_id: String,
+    pub opener: Pubkey,
+    pub participants: Vec<Pubkey>,
+    pub total_deposit: u64,
+}
+
+#[event]
+pub struct HubStateSubmittedEvent {
+    #[index]
+    pub hub_id: String,
+    pub submitter: Pubkey,
+    pub nonce: u64,
+    pub challenge_deadline: i64,
+}
+
+#[event]
+pub struct HubStateSignedEvent {
+    #[index]
+    pub hub_id: String,
+    pub nonce: u64,
+    pub signatures: u32,
+}
+
+#[event]
+pub struct HubWithdrawalEvent {
+    #[index]
+    pub hub_id: String,
+    pub participant: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct ChannelFeesWithdrawnEvent {
+    pub to: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct DisputePeriodBoundsUpdatedEvent {
+    pub min_dispute_period: i64,
+    pub max_dispute_period: i64,
+}
+
 #[event]
 pub struct ChannelFeeRateUpdatedEvent {
     pub new_rate: u16,
@@ -479,6 +1995,8 @@ pub enum ErrorCode {
     ChannelNotFound,
     #[msg("Channel not active")]
     ChannelNotActive,
+    #[msg("Channel close in progress")]
+    ChannelClosing,
     #[msg("Not channel participant")]
     NotChannelParticipant,
     #[msg("Nonce must be greater than current nonce")]
@@ -497,14 +2015,301 @@ pub enum ErrorCode {
     InsufficientFunds,
     #[msg("Rate too high")]
     RateTooHigh,
+    #[msg("Invalid amount")]
+    InvalidAmount,
+    #[msg("Dispute period out of bounds")]
+    InvalidDisputePeriod,
+    #[msg("Invalid dispute period bounds")]
+    InvalidDisputePeriodBounds,
     #[msg("Math overflow")]
     MathOverflow,
     #[msg("Math division error")]
     MathDivision,
+    #[msg("Watchtower cannot challenge a close initiated by its own participant")]
+    WatchtowerCannotChallengeOwnClose,
+    #[msg("Missing watchtower token account")]
+    MissingWatchtowerTokenAccount,
+    #[msg("Invalid hub participants")]
+    InvalidHubParticipants,
+    #[msg("Already withdrawn")]
+    AlreadyWithdrawn,
+    #[msg("Too many HTLCs")]
+    TooManyHtlcs,
+    #[msg("Invalid HTLC")]
+    InvalidHtlc,
+    #[msg("HTLC not found")]
+    HtlcNotFound,
+    #[msg("HTLC expired")]
+    HtlcExpired,
+    #[msg("HTLC not expired")]
+    HtlcNotExpired,
+    #[msg("Invalid preimage")]
+    InvalidPreimage,
+    #[msg("Missing Ed25519 signature instruction")]
+    MissingEd25519Instruction,
+    #[msg("Invalid Ed25519 signature instruction")]
+    InvalidEd25519Instruction,
+    #[msg("Missing state signature")]
+    MissingStateSignature,
+    #[msg("Watchtower bounty too high")]
+    BountyTooHigh,
+    #[msg("Bounty set without a watchtower")]
+    BountyWithoutWatchtower,
+    #[msg("Account already migrated")]
+    AlreadyMigrated,
+    #[msg("Account is not a valid pre-upgrade account")]
+    InvalidLegacyAccount,
+    #[msg("Unauthorized")]
+    Unauthorized,
+}
+
+// ============ Enums ============
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum HtlcDirection {
+    OneToTwo = 0,
+    TwoToOne = 1,
 }
 
 // ============ Utilities ============
 
+/// Top up rent from `payer` and zero-extend `account` to `new_len`
+fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+    new_len: usize,
+) -> Result<()> {
+    let required = Rent::get()?.minimum_balance(new_len);
+    let shortfall = required.saturating_sub(account.lamports());
+    if shortfall > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+    account.realloc(new_len, true)?;
+
+    Ok(())
+}
+
 fn get_channel_vault_pda(channel_id: &str) -> Pubkey {
     Pubkey::find_program_address(&[b"channel-vault", channel_id.as_bytes()], &ID).0
 }
+
+fn get_channel_treasury_pda(token_mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"channel-treasury", token_mint.as_ref()], &ID).0
+}
+
+fn get_hub_vault_pda(hub_id: &str) -> Pubkey {
+    Pubkey::find_program_address(&[b"hub-vault", hub_id.as_bytes()], &ID).0
+}
+
+fn counterparty_of(channel: &PaymentChannel, signer: Pubkey) -> Pubkey {
+    if signer == channel.participant1 {
+        channel.participant2
+    } else {
+        channel.participant1
+    }
+}
+
+/// Canonical channel state message that participants sign off-chain.
+/// Binds the program id and channel id so a signature cannot be replayed elsewhere.
+/// `deposited1`/`deposited2` are the per-side deposit totals the balances account for.
+pub fn channel_state_message(
+    channel_id: &str,
+    nonce: u64,
+    balance1: u64,
+    balance2: u64,
+    deposited1: u64,
+    deposited2: u64,
+    htlcs: &[Htlc],
+) -> [u8; 32] {
+    let balances = [balance1, balance2, deposited1, deposited2];
+    hash_channel_state(b"diap-channel-state", channel_id, nonce, &balances, htlcs)
+}
+
+/// Final state message for `cooperative_close`. Kept distinct from the regular
+/// state message so an ordinary co-signed update cannot be used to skip disputes.
+pub fn channel_close_message(
+    channel_id: &str,
+    nonce: u64,
+    balance1: u64,
+    balance2: u64,
+    deposited1: u64,
+    deposited2: u64,
+) -> [u8; 32] {
+    let balances = [balance1, balance2, deposited1, deposited2];
+    hash_channel_state(b"diap-channel-close", channel_id, nonce, &balances, &[])
+}
+
+fn hash_channel_state(
+    domain: &[u8],
+    channel_id: &str,
+    nonce: u64,
+    balances: &[u64; 4],
+    htlcs: &[Htlc],
+) -> [u8; 32] {
+    let mut htlc_bytes = Vec::with_capacity(htlcs.len() * Htlc::LEN);
+    for htlc in htlcs {
+        htlc_bytes.extend_from_slice(&htlc.amount.to_le_bytes());
+        htlc_bytes.extend_from_slice(&htlc.hashlock);
+        htlc_bytes.extend_from_slice(&htlc.expiry.to_le_bytes());
+        htlc_bytes.push(htlc.direction);
+    }
+
+    hashv(&[
+        domain,
+        ID.as_ref(),
+        channel_id.as_bytes(),
+        &nonce.to_le_bytes(),
+        &balances[0].to_le_bytes(),
+        &balances[1].to_le_bytes(),
+        &balances[2].to_le_bytes(),
+        &balances[3].to_le_bytes(),
+        &htlc_bytes,
+    ])
+    .to_bytes()
+}
+
+/// Check a co-signed state against the on-chain deposits and return the balances it
+/// settles to. The signed balances plus `locked` must add up exactly to the deposits the
+/// state accounts for; deposits made after it was signed go back to whoever made them.
+fn settle_signed_balances(
+    channel: &PaymentChannel,
+    balance1: u64,
+    balance2: u64,
+    deposited1: u64,
+    deposited2: u64,
+    locked: u64,
+) -> Result<(u64, u64)> {
+    let late_deposit1 = channel.deposited1
+        .checked_sub(deposited1)
+        .ok_or(ErrorCode::InvalidBalanceDistribution)?;
+    let late_deposit2 = channel.deposited2
+        .checked_sub(deposited2)
+        .ok_or(ErrorCode::InvalidBalanceDistribution)?;
+
+    let total_signed = balance1
+        .checked_add(balance2)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_add(locked)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let deposits_signed = deposited1.checked_add(deposited2).ok_or(ErrorCode::MathOverflow)?;
+    require!(total_signed == deposits_signed, ErrorCode::InvalidBalanceDistribution);
+
+    Ok((
+        balance1.checked_add(late_deposit1).ok_or(ErrorCode::MathOverflow)?,
+        balance2.checked_add(late_deposit2).ok_or(ErrorCode::MathOverflow)?,
+    ))
+}
+
+/// Share of `amount` at `rate` basis points, used for channel fees and watchtower bounties
+fn channel_fee(amount: u64, rate: u16) -> Result<u64> {
+    Ok(amount
+        .checked_mul(rate as u64)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(10000)
+        .ok_or(ErrorCode::MathDivision)?)
+}
+
+/// Canonical hub state message that participants sign off-chain
+pub fn hub_state_message(hub_id: &str, nonce: u64, balances: &[u64], total_deposited: u64) -> [u8; 32] {
+    let mut balance_bytes = Vec::with_capacity(balances.len() * 8);
+    for balance in balances {
+        balance_bytes.extend_from_slice(&balance.to_le_bytes());
+    }
+
+    hashv(&[
+        b"diap-hub-state",
+        ID.as_ref(),
+        hub_id.as_bytes(),
+        &nonce.to_le_bytes(),
+        &balance_bytes,
+        &total_deposited.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+/// Validate and record a submitted hub state. Every participant must have signed it
+/// through `sign_hub_state`, so its nonce can only move past a state the whole hub agreed on.
+fn apply_hub_state(
+    hub: &mut HubChannel,
+    hub_state: &HubStateSignatures,
+    submitter: Pubkey,
+    nonce: u64,
+    balances: Vec<u64>,
+) -> Result<()> {
+    require!(hub.participants.contains(&submitter), ErrorCode::NotChannelParticipant);
+    require!(nonce > hub.nonce, ErrorCode::NonceMustBeGreater);
+    validate_hub_balances(hub, &balances)?;
+    require!(hub_state.signed.iter().all(|signed| *signed), ErrorCode::MissingStateSignature);
+
+    hub.balances = balances;
+    hub.nonce = nonce;
+    hub.last_update = Clock::get()?.unix_timestamp;
+
+    Ok(())
+}
+
+/// Require `balances` to have one entry per participant and add up to the hub deposit
+fn validate_hub_balances(hub: &HubChannel, balances: &[u64]) -> Result<()> {
+    require!(balances.len() == hub.participants.len(), ErrorCode::InvalidBalanceDistribution);
+
+    let mut total: u64 = 0;
+    for balance in balances {
+        total = total.checked_add(*balance).ok_or(ErrorCode::MathOverflow)?;
+    }
+    require!(total == hub.total_deposited, ErrorCode::InvalidBalanceDistribution);
+
+    Ok(())
+}
+
+/// Check HTLC entries of a submitted state and return the total amount they lock
+fn validate_htlcs(htlcs: &[Htlc]) -> Result<u64> {
+    require!(htlcs.len() <= MAX_HTLCS, ErrorCode::TooManyHtlcs);
+
+    let mut total: u64 = 0;
+    for htlc in htlcs {
+        require!(
+            htlc.amount > 0 && htlc.direction <= HtlcDirection::TwoToOne as u8,
+            ErrorCode::InvalidHtlc
+        );
+        total = total.checked_add(htlc.amount).ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    Ok(total)
+}
+
+/// Load the signatures of the Ed25519 sigverify instruction preceding this one
+fn load_state_signatures(instructions: &AccountInfo) -> Result<Vec<Ed25519Signature>> {
+    load_ed25519_signatures(instructions).map_err(|error| match error {
+        CryptoError::MissingEd25519Instruction => error!(ErrorCode::MissingEd25519Instruction),
+        _ => error!(ErrorCode::InvalidEd25519Instruction),
+    })
+}
+
+/// Require the instruction preceding this one to be an Ed25519 sigverify
+/// instruction carrying a signature over `message` from every key in `signers`.
+fn verify_ed25519_signatures(
+    instructions: &AccountInfo,
+    signers: &[Pubkey],
+    message: &[u8],
+) -> Result<()> {
+    let signatures = load_state_signatures(instructions)?;
+
+    for signer in signers {
+        require!(
+            signatures.iter().any(|entry| entry.public_key == *signer && entry.message == message),
+            ErrorCode::MissingStateSignature
+        );
+    }
+
+    Ok(())
+}
@@ -3,8 +3,10 @@
 //! Core payment functionality including basic payments and escrow services.
 //! Adapted from Solidity DIAPPaymentCore.sol
 
+#![allow(unexpected_cfgs)]
+
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_spl::token::{self, CloseAccount, Mint, Token, TokenAccount, Transfer};
 
 declare_id!("HmbTLCmaGvZhKnn1Zfa1JVk7jmkAuCWx3nNSeXDVoEk1");
 
@@ -36,7 +38,7 @@ pub mod diap_payment_core {
         metadata: String,
     ) -> Result<()> {
         require!(amount > 0, ErrorCode::AmountMustBeGreaterThanZero);
-        require!(payment_id.len() > 0, ErrorCode::PaymentIDRequired);
+        require!(!payment_id.is_empty(), ErrorCode::PaymentIDRequired);
 
         let payment = &mut ctx.accounts.payment;
         require!(!payment.is_initialized, ErrorCode::PaymentIDAlreadyExists);
@@ -49,14 +51,26 @@ pub mod diap_payment_core {
         // In real implementation, you would call the diap-agent-network program to verify
         // that these are valid, active agent accounts
         
+        let fee = amount
+            .checked_mul(ctx.accounts.payment_core.payment_fee_rate as u64)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(10000)
+            .ok_or(ErrorCode::MathDivision)?;
+        let total = amount.checked_add(fee).ok_or(ErrorCode::MathOverflow)?;
+
         // Initialize payment
         payment.from = from.key();
         payment.to = to.key();
+        payment.payer = ctx.accounts.payer.key();
         payment.amount = amount;
+        payment.fee = fee;
         payment.payment_id = payment_id.clone();
         payment.description = description;
         payment.metadata = metadata;
         payment.timestamp = Clock::get()?.unix_timestamp;
+        payment.expires_at = payment.timestamp
+            .checked_add(PAYMENT_CONFIRM_WINDOW)
+            .ok_or(ErrorCode::MathOverflow)?;
         payment.status = PaymentStatus::Pending as u8;
         payment.bump = ctx.bumps.payment;
         payment.is_initialized = true;
@@ -65,6 +79,16 @@ pub mod diap_payment_core {
         let core = &mut ctx.accounts.payment_core;
         core.total_payments = core.total_payments.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
 
+        // Lock amount + fee from payer into the payment vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.payer_token_account.to_account_info(),
+            to: ctx.accounts.payment_vault.to_account_info(),
+            authority: ctx.accounts.payer.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, total)?;
+
         emit!(PaymentCreatedEvent {
             payment_id: payment_id.clone(),
             from: from.key(),
@@ -80,22 +104,55 @@ pub mod diap_payment_core {
         
         require!(payment.is_initialized, ErrorCode::PaymentNotFound);
         require!(payment.status == PaymentStatus::Pending as u8, ErrorCode::PaymentNotPending);
+        require!(Clock::get()?.unix_timestamp <= payment.expires_at, ErrorCode::PaymentExpired);
         
-        // In real implementation, would check that the confirm is from the recipient
-        // and that the payment is not expired (e.g., 24 hours has not passed)
-        
-        // Calculate fee and total
-        let fee = payment.amount
-            .checked_mul(ctx.accounts.payment_core.payment_fee_rate as u64)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(10000)
-            .ok_or(ErrorCode::MathDivision)?;
-        
-        // For this simplified version, the payment is just updated to confirmed
-        // In a real implementation, the token transfer would happen here
         payment.status = PaymentStatus::Confirmed as u8;
 
-        ctx.accounts.payment_core.total_volume = ctx.accounts.payment_core.total_volume.checked_add(payment.amount).ok_or(ErrorCode::MathOverflow)?;
+        let amount = payment.amount;
+        let fee = payment.fee;
+
+        let core = &ctx.accounts.payment_core;
+        let seeds = &[
+            b"payment-core",
+            core.token_mint.as_ref(),
+            &[core.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        // Release amount to recipient
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.payment_vault.to_account_info(),
+            to: ctx.accounts.recipient_token_account.to_account_info(),
+            authority: core.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, amount)?;
+
+        // Route fee to fee vault
+        if fee > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.payment_vault.to_account_info(),
+                to: ctx.accounts.fee_vault.to_account_info(),
+                authority: core.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, fee)?;
+        }
+
+        // Close the emptied vault, returning its rent to the payer
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.payment_vault.to_account_info(),
+            destination: ctx.accounts.payer.to_account_info(),
+            authority: core.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::close_account(cpi_ctx)?;
+
+        let core = &mut ctx.accounts.payment_core;
+        core.total_volume = core.total_volume.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
 
         emit!(PaymentConfirmedEvent {
             payment_id: payment.payment_id.clone(),
@@ -111,10 +168,38 @@ pub mod diap_payment_core {
         require!(payment.is_initialized, ErrorCode::PaymentNotFound);
         require!(payment.status == PaymentStatus::Pending as u8, ErrorCode::PaymentNotPending);
         
-        // In real implementation, would check that the cancel is from the sender
-        
         payment.status = PaymentStatus::Cancelled as u8;
 
+        let refund_amount = payment.amount.checked_add(payment.fee).ok_or(ErrorCode::MathOverflow)?;
+
+        let core = &ctx.accounts.payment_core;
+        let seeds = &[
+            b"payment-core",
+            core.token_mint.as_ref(),
+            &[core.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        // Refund amount + fee to payer
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.payment_vault.to_account_info(),
+            to: ctx.accounts.payer_token_account.to_account_info(),
+            authority: core.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, refund_amount)?;
+
+        // Close the emptied vault; the payment account itself is closed by Anchor
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.payment_vault.to_account_info(),
+            destination: ctx.accounts.payer.to_account_info(),
+            authority: core.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::close_account(cpi_ctx)?;
+
         emit!(PaymentCancelledEvent {
             payment_id: payment.payment_id.clone(),
             reason: "Cancelled by sender".to_string(),
@@ -123,13 +208,64 @@ pub mod diap_payment_core {
         Ok(())
     }
 
+    /// Move a pending payment past its confirmation window into a terminal state.
+    /// Permissionless so that stale payments can be cranked by anyone.
+    pub fn expire_payment(ctx: Context<ExpirePayment>) -> Result<()> {
+        let payment = &mut ctx.accounts.payment;
+
+        require!(payment.is_initialized, ErrorCode::PaymentNotFound);
+        require!(payment.status == PaymentStatus::Pending as u8, ErrorCode::PaymentNotPending);
+
+        let clock = Clock::get()?;
+        require!(clock.unix_timestamp > payment.expires_at, ErrorCode::PaymentNotExpired);
+
+        payment.status = PaymentStatus::Expired as u8;
+
+        let refund_amount = payment.amount.checked_add(payment.fee).ok_or(ErrorCode::MathOverflow)?;
+
+        // Refund amount + fee to payer
+        let core = &ctx.accounts.payment_core;
+        let seeds = &[
+            b"payment-core",
+            core.token_mint.as_ref(),
+            &[core.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.payment_vault.to_account_info(),
+            to: ctx.accounts.payer_token_account.to_account_info(),
+            authority: core.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, refund_amount)?;
+
+        // Close the emptied vault, returning its rent to the payer
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.payment_vault.to_account_info(),
+            destination: ctx.accounts.payer.to_account_info(),
+            authority: core.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::close_account(cpi_ctx)?;
+
+        emit!(PaymentExpiredEvent {
+            payment_id: payment.payment_id.clone(),
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
     pub fn create_service_order(
         ctx: Context<CreateServiceOrder>,
         price: u64,
         service_type_cid: String,
     ) -> Result<()> {
         require!(price > 0, ErrorCode::InvalidPrice);
-        require!(service_type_cid.len() > 0, ErrorCode::ServiceTypeCIDRequired);
+        require!(!service_type_cid.is_empty(), ErrorCode::ServiceTypeCIDRequired);
 
         let service = &mut ctx.accounts.service;
         require!(!service.is_initialized, ErrorCode::ServiceAlreadyExists);
@@ -147,11 +283,17 @@ pub mod diap_payment_core {
             .ok_or(ErrorCode::MathDivision)?;
         let escrow_amount = price.checked_add(fee).ok_or(ErrorCode::MathOverflow)?;
 
+        let service_id = ctx.accounts.payment_core.total_services;
+
+        service.service_id = service_id;
         service.provider = provider.key();
         service.consumer = consumer.key();
         service.price = price;
         service.escrowed_amount = escrow_amount;
         service.timestamp = clock.unix_timestamp;
+        service.expires_at = clock.unix_timestamp
+            .checked_add(SERVICE_COMPLETION_WINDOW)
+            .ok_or(ErrorCode::MathOverflow)?;
         service.completion_time = 0;
         service.status = ServiceStatus::Escrowed as u8;
         service.service_type_cid = service_type_cid;
@@ -162,6 +304,16 @@ pub mod diap_payment_core {
         let core = &mut ctx.accounts.payment_core;
         core.total_services = core.total_services.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
 
+        // Lock price + fee from consumer into the service escrow vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.consumer_token_account.to_account_info(),
+            to: ctx.accounts.service_escrow.to_account_info(),
+            authority: consumer.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, escrow_amount)?;
+
         emit!(ServiceCreatedEvent {
             service_id: service.key(),
             provider: provider.key(),
@@ -180,20 +332,51 @@ pub mod diap_payment_core {
         
         require!(service.is_initialized, ErrorCode::ServiceNotFound);
         require!(service.status == ServiceStatus::Escrowed as u8, ErrorCode::ServiceNotEscrowed);
-        require!(result_cid.len() > 0, ErrorCode::InvalidResultCID);
-        
-        // In real implementation, would check that the completion is from the provider
-        // and that the service is not expired (e.g., 30 days has not passed)
-        
-        let clock = Clock::get()?;
+        require!(!result_cid.is_empty(), ErrorCode::InvalidResultCID);
         
         let clock = Clock::get()?;
+        require!(clock.unix_timestamp <= service.expires_at, ErrorCode::ServiceExpired);
+
         service.status = ServiceStatus::Completed as u8;
         service.completion_time = clock.unix_timestamp;
         service.result_cid = result_cid;
 
+        let price = service.price;
+        let fee = service.escrowed_amount.checked_sub(price).ok_or(ErrorCode::MathUnderflow)?;
+        service.escrowed_amount = 0;
+
+        let core = &ctx.accounts.payment_core;
+        let seeds = &[
+            b"payment-core",
+            core.token_mint.as_ref(),
+            &[core.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        // Release price to provider
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.service_escrow.to_account_info(),
+            to: ctx.accounts.provider_token_account.to_account_info(),
+            authority: core.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, price)?;
+
+        // Route fee to fee vault
+        if fee > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.service_escrow.to_account_info(),
+                to: ctx.accounts.fee_vault.to_account_info(),
+                authority: core.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, fee)?;
+        }
+
         let core = &mut ctx.accounts.payment_core;
-        core.total_volume = core.total_volume.checked_add(service.price).ok_or(ErrorCode::MathOverflow)?;
+        core.total_volume = core.total_volume.checked_add(price).ok_or(ErrorCode::MathOverflow)?;
 
         emit!(ServiceCompletedEvent {
             service_id: service.key(),
@@ -210,12 +393,28 @@ pub mod diap_payment_core {
         require!(service.is_initialized, ErrorCode::ServiceNotFound);
         require!(service.status == ServiceStatus::Escrowed as u8, ErrorCode::ServiceNotEscrowed);
         
-        // In real implementation, would check that the cancel is from the consumer
-        // and that the cancellation period (e.g., 24 hours) has not passed
-        
         service.status = ServiceStatus::Cancelled as u8;
         
         let refund_amount = service.escrowed_amount;
+        service.escrowed_amount = 0;
+
+        // Refund the full escrow to consumer
+        let core = &ctx.accounts.payment_core;
+        let seeds = &[
+            b"payment-core",
+            core.token_mint.as_ref(),
+            &[core.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.service_escrow.to_account_info(),
+            to: ctx.accounts.consumer_token_account.to_account_info(),
+            authority: core.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, refund_amount)?;
         
         emit!(ServiceCancelledEvent {
             service_id: service.key(),
@@ -225,6 +424,48 @@ pub mod diap_payment_core {
         Ok(())
     }
 
+    /// Refund the consumer of an escrowed service order whose completion window has passed.
+    /// Permissionless so that stale orders can be cranked by anyone.
+    pub fn expire_service_order(ctx: Context<ExpireServiceOrder>) -> Result<()> {
+        let service = &mut ctx.accounts.service;
+
+        require!(service.is_initialized, ErrorCode::ServiceNotFound);
+        require!(service.status == ServiceStatus::Escrowed as u8, ErrorCode::ServiceNotEscrowed);
+
+        let clock = Clock::get()?;
+        require!(clock.unix_timestamp > service.expires_at, ErrorCode::ServiceNotExpired);
+
+        service.status = ServiceStatus::Expired as u8;
+
+        let refund_amount = service.escrowed_amount;
+        service.escrowed_amount = 0;
+
+        // Refund the full escrow to consumer
+        let core = &ctx.accounts.payment_core;
+        let seeds = &[
+            b"payment-core",
+            core.token_mint.as_ref(),
+            &[core.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.service_escrow.to_account_info(),
+            to: ctx.accounts.consumer_token_account.to_account_info(),
+            authority: core.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, refund_amount)?;
+
+        emit!(ServiceExpiredEvent {
+            service_id: service.key(),
+            refund_amount,
+        });
+
+        Ok(())
+    }
+
     pub fn update_fee_rate(ctx: Context<UpdateFeeRate>, new_rate: u16) -> Result<()> {
         require!(new_rate <= 100, ErrorCode::RateTooHigh);
         
@@ -237,6 +478,42 @@ pub mod diap_payment_core {
 
         Ok(())
     }
+
+    /// Create the fee vault for a `PaymentCore` initialized before fees were
+    /// collected on-chain (authority only)
+    pub fn init_fee_vault(_ctx: Context<InitFeeVault>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Withdraw collected fees from the fee vault (authority only)
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(ctx.accounts.fee_vault.amount >= amount, ErrorCode::InsufficientFees);
+
+        let core = &ctx.accounts.payment_core;
+        let seeds = &[
+            b"payment-core",
+            core.token_mint.as_ref(),
+            &[core.bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.fee_vault.to_account_info(),
+            to: ctx.accounts.destination_token_account.to_account_info(),
+            authority: core.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, amount)?;
+
+        emit!(FeesWithdrawnEvent {
+            to: ctx.accounts.destination_token_account.key(),
+            amount,
+        });
+
+        Ok(())
+    }
 }
 
 // ============ Accounts ============
@@ -252,11 +529,22 @@ pub struct Initialize<'info> {
     )]
     pub payment_core: Account<'info, PaymentCore>,
     
+    #[account(
+        init,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = payment_core,
+        seeds = [b"fee-vault", token_mint.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    
     pub token_mint: Account<'info, Mint>,
     
     #[account(mut)]
     pub authority: Signer<'info>,
     
+    pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 }
 
@@ -286,11 +574,29 @@ pub struct CreatePayment<'info> {
     /// CHECK: Recipient address (should be an active agent in real implementation)
     pub to: UncheckedAccount<'info>,
 
+    #[account(
+        init,
+        payer = payer,
+        token::mint = token_mint,
+        token::authority = payment_core,
+        seeds = [b"payment-vault", payment.key().as_ref()],
+        bump
+    )]
+    pub payment_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = payer
+    )]
+    pub payer_token_account: Account<'info, TokenAccount>,
+
     pub token_mint: Account<'info, Mint>,
 
     #[account(mut)]
     pub payer: Signer<'info>,
 
+    pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 }
 
@@ -310,10 +616,37 @@ pub struct ConfirmPayment<'info> {
     )]
     pub payment_core: Account<'info, PaymentCore>,
     
+    #[account(
+        mut,
+        constraint = payment_vault.key() == get_payment_vault_pda(&payment.key())
+    )]
+    pub payment_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = payment.to
+    )]
+    pub recipient_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = fee_vault.key() == get_fee_vault_pda(&token_mint.key())
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    
     pub token_mint: Account<'info, Mint>,
     
-    /// CHECK: Should be the recipient of the payment
+    /// CHECK: Original payer, receives the vault's rent
+    #[account(mut, address = payment.payer)]
+    pub payer: UncheckedAccount<'info>,
+    
+    #[account(
+        constraint = recipient.key() == payment.to @ ErrorCode::NotPaymentRecipient
+    )]
     pub recipient: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
@@ -321,12 +654,80 @@ pub struct CancelPayment<'info> {
     #[account(
         mut,
         seeds = [b"payment", payment.payment_id.as_bytes()],
-        bump = payment.bump
+        bump = payment.bump,
+        has_one = payer,
+        close = payer
     )]
     pub payment: Account<'info, Payment>,
     
-    /// CHECK: Should be the sender of the payment
+    #[account(
+        seeds = [b"payment-core", token_mint.key().as_ref()],
+        bump = payment_core.bump
+    )]
+    pub payment_core: Account<'info, PaymentCore>,
+    
+    #[account(
+        mut,
+        constraint = payment_vault.key() == get_payment_vault_pda(&payment.key())
+    )]
+    pub payment_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = payment.payer
+    )]
+    pub payer_token_account: Account<'info, TokenAccount>,
+    
+    pub token_mint: Account<'info, Mint>,
+    
+    /// CHECK: Original payer, receives the refunded rent
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+    
+    #[account(
+        constraint = sender.key() == payment.from @ ErrorCode::NotPaymentSender
+    )]
     pub sender: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExpirePayment<'info> {
+    #[account(
+        mut,
+        seeds = [b"payment", payment.payment_id.as_bytes()],
+        bump = payment.bump
+    )]
+    pub payment: Account<'info, Payment>,
+    
+    #[account(
+        seeds = [b"payment-core", token_mint.key().as_ref()],
+        bump = payment_core.bump
+    )]
+    pub payment_core: Account<'info, PaymentCore>,
+    
+    #[account(
+        mut,
+        constraint = payment_vault.key() == get_payment_vault_pda(&payment.key())
+    )]
+    pub payment_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = payment.payer
+    )]
+    pub payer_token_account: Account<'info, TokenAccount>,
+    
+    pub token_mint: Account<'info, Mint>,
+    
+    /// CHECK: Original payer, receives the vault's rent
+    #[account(mut, address = payment.payer)]
+    pub payer: UncheckedAccount<'info>,
+    
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
@@ -347,6 +748,23 @@ pub struct CreateServiceOrder<'info> {
     )]
     pub payment_core: Account<'info, PaymentCore>,
     
+    #[account(
+        init,
+        payer = consumer,
+        token::mint = token_mint,
+        token::authority = payment_core,
+        seeds = [b"service-escrow", service.key().as_ref()],
+        bump
+    )]
+    pub service_escrow: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = consumer
+    )]
+    pub consumer_token_account: Account<'info, TokenAccount>,
+    
     /// CHECK: Provider address (should be an active agent in real implementation)
     pub provider: UncheckedAccount<'info>,
     
@@ -356,6 +774,7 @@ pub struct CreateServiceOrder<'info> {
     
     pub token_mint: Account<'info, Mint>,
     
+    pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 }
 
@@ -363,7 +782,7 @@ pub struct CreateServiceOrder<'info> {
 pub struct CompleteServiceOrder<'info> {
     #[account(
         mut,
-        seeds = [b"service", service.key().as_ref()],
+        seeds = [b"service", service.service_id.to_le_bytes().as_ref()],
         bump = service.bump
     )]
     pub service: Account<'info, Service>,
@@ -375,23 +794,104 @@ pub struct CompleteServiceOrder<'info> {
     )]
     pub payment_core: Account<'info, PaymentCore>,
     
+    #[account(
+        mut,
+        constraint = service_escrow.key() == get_service_escrow_pda(&service.key())
+    )]
+    pub service_escrow: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = service.provider
+    )]
+    pub provider_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = fee_vault.key() == get_fee_vault_pda(&token_mint.key())
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    
     pub token_mint: Account<'info, Mint>,
     
-    /// CHECK: Should be the provider of the service
+    #[account(
+        constraint = provider.key() == service.provider @ ErrorCode::NotServiceProvider
+    )]
     pub provider: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
 pub struct CancelServiceOrder<'info> {
     #[account(
         mut,
-        seeds = [b"service", service.key().as_ref()],
+        seeds = [b"service", service.service_id.to_le_bytes().as_ref()],
         bump = service.bump
     )]
     pub service: Account<'info, Service>,
     
-    /// CHECK: Should be the consumer of the service
+    #[account(
+        seeds = [b"payment-core", token_mint.key().as_ref()],
+        bump = payment_core.bump
+    )]
+    pub payment_core: Account<'info, PaymentCore>,
+    
+    #[account(
+        mut,
+        constraint = service_escrow.key() == get_service_escrow_pda(&service.key())
+    )]
+    pub service_escrow: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = service.consumer
+    )]
+    pub consumer_token_account: Account<'info, TokenAccount>,
+    
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(
+        constraint = consumer.key() == service.consumer @ ErrorCode::NotServiceConsumer
+    )]
     pub consumer: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExpireServiceOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"service", service.service_id.to_le_bytes().as_ref()],
+        bump = service.bump
+    )]
+    pub service: Account<'info, Service>,
+    
+    #[account(
+        seeds = [b"payment-core", token_mint.key().as_ref()],
+        bump = payment_core.bump
+    )]
+    pub payment_core: Account<'info, PaymentCore>,
+    
+    #[account(
+        mut,
+        constraint = service_escrow.key() == get_service_escrow_pda(&service.key())
+    )]
+    pub service_escrow: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = service.consumer
+    )]
+    pub consumer_token_account: Account<'info, TokenAccount>,
+    
+    pub token_mint: Account<'info, Mint>,
+    
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
@@ -409,8 +909,65 @@ pub struct UpdateFeeRate<'info> {
     pub authority: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct InitFeeVault<'info> {
+    #[account(
+        seeds = [b"payment-core", token_mint.key().as_ref()],
+        bump = payment_core.bump,
+        has_one = authority
+    )]
+    pub payment_core: Account<'info, PaymentCore>,
+    
+    #[account(
+        init,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = payment_core,
+        seeds = [b"fee-vault", token_mint.key().as_ref()],
+        bump
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFees<'info> {
+    #[account(
+        seeds = [b"payment-core", payment_core.token_mint.as_ref()],
+        bump = payment_core.bump,
+        has_one = authority
+    )]
+    pub payment_core: Account<'info, PaymentCore>,
+    
+    #[account(
+        mut,
+        constraint = fee_vault.key() == get_fee_vault_pda(&payment_core.token_mint)
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = payment_core.token_mint
+    )]
+    pub destination_token_account: Account<'info, TokenAccount>,
+    
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
 // ============ State ============
 
+const PAYMENT_CONFIRM_WINDOW: i64 = 24 * 60 * 60; // 24 hours
+const SERVICE_COMPLETION_WINDOW: i64 = 30 * 24 * 60 * 60; // 30 days
+
 #[account]
 pub struct PaymentCore {
     pub authority: Pubkey,
@@ -430,27 +987,32 @@ impl PaymentCore {
 pub struct Payment {
     pub from: Pubkey,
     pub to: Pubkey,
+    pub payer: Pubkey,
     pub amount: u64,
+    pub fee: u64,
     pub payment_id: String,
     pub description: String,
     pub metadata: String,
     pub timestamp: i64,
+    pub expires_at: i64,
     pub status: u8,
     pub bump: u8,
     pub is_initialized: bool,
 }
 
 impl Payment {
-    pub const LEN: usize = 32 + 32 + 8 + 100 + 200 + 200 + 8 + 1 + 1 + 1;
+    pub const LEN: usize = 32 + 32 + 32 + 8 + 8 + 100 + 200 + 200 + 8 + 8 + 1 + 1 + 1;
 }
 
 #[account]
 pub struct Service {
+    pub service_id: u64,
     pub provider: Pubkey,
     pub consumer: Pubkey,
     pub price: u64,
     pub escrowed_amount: u64,
     pub timestamp: i64,
+    pub expires_at: i64,
     pub completion_time: i64,
     pub status: u8,
     pub service_type_cid: String,
@@ -460,7 +1022,7 @@ pub struct Service {
 }
 
 impl Service {
-    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8 + 1 + 200 + 200 + 1 + 1;
+    pub const LEN: usize = 8 + 32 + 32 + 8 + 8 + 8 + 8 + 8 + 1 + 200 + 200 + 1 + 1;
 }
 
 // ============ Events ============
@@ -488,6 +1050,13 @@ pub struct PaymentCancelledEvent {
     pub reason: String,
 }
 
+#[event]
+pub struct PaymentExpiredEvent {
+    #[index]
+    pub payment_id: String,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct ServiceCreatedEvent {
     #[index]
@@ -512,11 +1081,24 @@ pub struct ServiceCancelledEvent {
     pub refund_amount: u64,
 }
 
+#[event]
+pub struct ServiceExpiredEvent {
+    #[index]
+    pub service_id: Pubkey,
+    pub refund_amount: u64,
+}
+
 #[event]
 pub struct FeeRateUpdatedEvent {
     pub new_rate: u16,
 }
 
+#[event]
+pub struct FeesWithdrawnEvent {
+    pub to: Pubkey,
+    pub amount: u64,
+}
+
 // ============ Errors ============
 
 #[error_code]
@@ -531,6 +1113,14 @@ pub enum ErrorCode {
     PaymentNotFound,
     #[msg("Payment not pending")]
     PaymentNotPending,
+    #[msg("Payment expired")]
+    PaymentExpired,
+    #[msg("Payment not expired")]
+    PaymentNotExpired,
+    #[msg("Not payment sender")]
+    NotPaymentSender,
+    #[msg("Not payment recipient")]
+    NotPaymentRecipient,
     #[msg("Invalid price")]
     InvalidPrice,
     #[msg("Service type CID required")]
@@ -543,14 +1133,26 @@ pub enum ErrorCode {
     InvalidResultCID,
     #[msg("Service expired")]
     ServiceExpired,
+    #[msg("Service not expired")]
+    ServiceNotExpired,
+    #[msg("Not service provider")]
+    NotServiceProvider,
+    #[msg("Not service consumer")]
+    NotServiceConsumer,
     #[msg("Service already exists")]
     ServiceAlreadyExists,
     #[msg("Rate too high")]
     RateTooHigh,
     #[msg("Math overflow")]
     MathOverflow,
+    #[msg("Math underflow")]
+    MathUnderflow,
     #[msg("Math division error")]
     MathDivision,
+    #[msg("Invalid amount")]
+    InvalidAmount,
+    #[msg("Insufficient fees")]
+    InsufficientFees,
 }
 
 // ============ Enums ============
@@ -561,6 +1163,7 @@ pub enum PaymentStatus {
     Confirmed = 1,
     Failed = 2,
     Cancelled = 3,
+    Expired = 4,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
@@ -570,4 +1173,19 @@ pub enum ServiceStatus {
     Active = 2,
     Completed = 3,
     Cancelled = 4,
+    Expired = 5,
+}
+
+// ============ Utilities ============
+
+fn get_payment_vault_pda(payment: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"payment-vault", payment.as_ref()], &ID).0
+}
+
+fn get_service_escrow_pda(service: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"service-escrow", service.as_ref()], &ID).0
+}
+
+fn get_fee_vault_pda(token_mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"fee-vault", token_mint.as_ref()], &ID).0
 }
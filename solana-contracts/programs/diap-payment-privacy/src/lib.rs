@@ -3,8 +3,14 @@
 //! Privacy-preserving payments using commitments and nullifiers.
 //! Adapted from Solidity DIAPPaymentPrivacy.sol
 
+#![allow(unexpected_cfgs)]
+
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::poseidon::{self, Endianness, Parameters};
+use anchor_lang::Discriminator;
+use anchor_spl::token::{self, CloseAccount, Mint, Token, TokenAccount, Transfer};
+use diap_crypto::{CryptoError, Groth16ProofPoints, Groth16VerifyingKey, BN254_SCALAR_MODULUS};
 
 declare_id!("11111111111111111111111111111111");
 
@@ -23,35 +29,135 @@ pub mod diap_payment_privacy {
         Ok(())
     }
 
+    /// Create a fixed denomination pool with its own tree and vault (authority only)
+    pub fn create_denomination_pool(
+        ctx: Context<CreateDenominationPool>,
+        denomination: u64,
+    ) -> Result<()> {
+        require!(denomination > 0, ErrorCode::AmountMustBeGreaterThanZero);
+
+        let pool = &mut ctx.accounts.pool;
+        pool.privacy_payment = ctx.accounts.privacy_payment.key();
+        pool.denomination = denomination;
+        pool.anonymity_set_size = 0;
+        pool.is_active = true;
+        pool.bump = ctx.bumps.pool;
+
+        let merkle_tree = &mut ctx.accounts.merkle_tree;
+        merkle_tree.init(ctx.bumps.merkle_tree)?;
+
+        emit!(DenominationPoolCreatedEvent {
+            pool: pool.key(),
+            denomination,
+        });
+
+        Ok(())
+    }
+
+    /// Open or close a denomination pool for new deposits (authority only)
+    pub fn set_pool_status(ctx: Context<SetPoolStatus>, is_active: bool) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.is_active = is_active;
+
+        emit!(PoolStatusChangedEvent {
+            pool: pool.key(),
+            is_active,
+        });
+
+        Ok(())
+    }
+
+    /// Set or rotate the Groth16 verifying key used for privacy payments (authority only)
+    pub fn set_verifying_key(
+        ctx: Context<SetVerifyingKey>,
+        alpha_g1: [u8; 64],
+        beta_g2: [u8; 128],
+        gamma_g2: [u8; 128],
+        delta_g2: [u8; 128],
+        ic: Vec<[u8; 64]>,
+    ) -> Result<()> {
+        require!(
+            ic.len() >= 2 && ic.len() <= MAX_PUBLIC_INPUTS + 1,
+            ErrorCode::InvalidVerifyingKey
+        );
+
+        let verifying_key = &mut ctx.accounts.verifying_key;
+        verifying_key.privacy_payment = ctx.accounts.privacy_payment.key();
+        verifying_key.alpha_g1 = alpha_g1;
+        verifying_key.beta_g2 = beta_g2;
+        verifying_key.gamma_g2 = gamma_g2;
+        verifying_key.delta_g2 = delta_g2;
+        verifying_key.ic = ic;
+        verifying_key.bump = ctx.bumps.verifying_key;
+
+        emit!(VerifyingKeyUpdatedEvent {
+            authority: ctx.accounts.authority.key(),
+            public_inputs: (verifying_key.ic.len() - 1) as u8,
+        });
+
+        Ok(())
+    }
+
+    /// Register or rotate the caller's x25519 viewing key. Notes can carry a
+    /// memo encrypted to this key so the owner can disclose their history to auditors.
+    pub fn register_view_key(ctx: Context<RegisterViewKey>, view_key: [u8; 32]) -> Result<()> {
+        require!(view_key != [0u8; 32], ErrorCode::InvalidViewKey);
+
+        let view_key_record = &mut ctx.accounts.view_key_record;
+        view_key_record.owner = ctx.accounts.owner.key();
+        view_key_record.view_key = view_key;
+        view_key_record.updated_at = Clock::get()?.unix_timestamp;
+        view_key_record.bump = ctx.bumps.view_key_record;
+
+        emit!(ViewKeyRegisteredEvent {
+            owner: ctx.accounts.owner.key(),
+            view_key,
+        });
+
+        Ok(())
+    }
+
+    /// Deposit one denomination into a pool. The tree leaf is computed here as
+    /// `Poseidon(commitment, denomination)` so a note's value is bound to what was
+    /// actually deposited; change notes use the same leaf form, built by the spend circuit.
     pub fn lock_funds_for_privacy(
         ctx: Context<LockFundsForPrivacy>,
         commitment: [u8; 32],
         amount: u64,
+        encrypted_memo: Option<Vec<u8>>,
     ) -> Result<()> {
         require!(amount > 0, ErrorCode::AmountMustBeGreaterThanZero);
+        check_memo(&encrypted_memo)?;
         require!(commitment != [0u8; 32], ErrorCode::InvalidCommitment);
+        require!(commitment < BN254_SCALAR_MODULUS, ErrorCode::InvalidCommitment);
+
+        let pool = &mut ctx.accounts.pool;
+        require!(pool.is_active, ErrorCode::PoolNotActive);
+        require!(amount == pool.denomination, ErrorCode::InvalidDenomination);
 
         let privacy_payment = &mut ctx.accounts.privacy_payment;
+        let merkle_tree = &mut ctx.accounts.merkle_tree;
         let commitment_record = &mut ctx.accounts.commitment_record;
         
         require!(!commitment_record.is_initialized, ErrorCode::CommitmentAlreadyExists);
 
         let clock = Clock::get()?;
+        let leaf = poseidon_hash(&commitment, &u64_to_field(pool.denomination))?;
+        let leaf_index = merkle_tree.insert(leaf)?;
 
         commitment_record.commitment = commitment;
-        commitment_record.amount = amount;
-        commitment_record.owner = ctx.accounts.owner.key();
+        commitment_record.leaf_index = leaf_index;
         commitment_record.timestamp = clock.unix_timestamp;
-        commitment_record.is_used = false;
         commitment_record.is_initialized = true;
         commitment_record.bump = ctx.bumps.commitment_record;
 
         privacy_payment.total_commitments = privacy_payment.total_commitments.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        pool.anonymity_set_size = pool.anonymity_set_size.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
 
-        // Transfer tokens from owner to vault
+        // Transfer tokens from owner to the pool
         let cpi_accounts = Transfer {
             from: ctx.accounts.owner_token_account.to_account_info(),
-            to: ctx.accounts.commitment_vault.to_account_info(),
+            to: ctx.accounts.pool_vault.to_account_info(),
             authority: ctx.accounts.owner.to_account_info(),
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
@@ -59,46 +165,85 @@ pub mod diap_payment_privacy {
         token::transfer(cpi_ctx, amount)?;
 
         emit!(FundsLockedEvent {
+            pool: pool.key(),
             commitment,
+            leaf,
+            leaf_index,
+            root: merkle_tree.current_root(),
             amount,
             locker: ctx.accounts.owner.key(),
+            encrypted_memo,
         });
 
         Ok(())
     }
 
+    /// Spend a note from the pool by proving membership against a recent root.
+    /// Leaves are `Poseidon(commitment, value)`, and the circuit enforces
+    /// `amount + change value == value`, with `change_commitment` being the change
+    /// note's leaf. The unspent remainder goes into that change note in the same pool;
+    /// full spends still create a zero-value change note so every spend looks alike.
+    /// The transaction is submitted by a relayer, who pays rent and receives
+    /// `relayer_fee` out of `amount`; both are bound by the proof, as is the
+    /// owner of `recipient_token_account`, so a copied proof cannot be redirected.
+    #[allow(clippy::too_many_arguments)]
     pub fn execute_privacy_payment(
         ctx: Context<ExecutePrivacyPayment>,
-        commitment: [u8; 32],
+        root: [u8; 32],
         nullifier: [u8; 32],
-        proof: [u8; 8],
-        to: Pubkey,
+        change_commitment: [u8; 32],
+        proof: Groth16Proof,
         amount: u64,
+        relayer_fee: u64,
+        encrypted_memo: Option<Vec<u8>>,
     ) -> Result<()> {
         let privacy_payment = &mut ctx.accounts.privacy_payment;
-        let commitment_record = &mut ctx.accounts.commitment_record;
+        let pool = &mut ctx.accounts.pool;
+        let merkle_tree = &mut ctx.accounts.merkle_tree;
         let nullifier_record = &mut ctx.accounts.nullifier_record;
+        let change_record = &mut ctx.accounts.change_commitment_record;
+        let to = ctx.accounts.recipient_token_account.owner;
 
         require!(amount > 0, ErrorCode::AmountMustBeGreaterThanZero);
-        require!(commitment_record.is_initialized, ErrorCode::CommitmentNotFound);
-        require!(!commitment_record.is_used, ErrorCode::CommitmentAlreadyUsed);
         require!(!nullifier_record.is_used, ErrorCode::NullifierAlreadyUsed);
-        require!(amount <= commitment_record.amount, ErrorCode::InsufficientLockedFunds);
-
-        // Verify ZKP proof
-        require!(proof != [0u8; 8], ErrorCode::InvalidPrivacyProof);
+        require!(amount <= pool.denomination, ErrorCode::InvalidDenomination);
+        require!(relayer_fee <= amount, ErrorCode::RelayerFeeTooHigh);
+        require!(merkle_tree.is_known_root(&root), ErrorCode::UnknownMerkleRoot);
+        require!(change_commitment != [0u8; 32], ErrorCode::InvalidCommitment);
+        require!(change_commitment < BN254_SCALAR_MODULUS, ErrorCode::InvalidCommitment);
+        check_memo(&encrypted_memo)?;
+
+        // Verify ZKP proof. The note value is bound by its leaf, and the circuit
+        // enforces note value == amount + change value.
+        let public_inputs = [
+            root,
+            nullifier,
+            pubkey_to_field(&to),
+            u64_to_field(amount),
+            change_commitment,
+            pubkey_to_field(&ctx.accounts.relayer.key()),
+            u64_to_field(relayer_fee),
+        ];
+        verify_groth16_proof(&ctx.accounts.verifying_key, &proof, &public_inputs)?;
 
-        commitment_record.is_used = true;
         nullifier_record.nullifier = nullifier;
         nullifier_record.is_used = true;
-        nullifier_record.commitment = commitment;
+        nullifier_record.bump = ctx.bumps.nullifier_record;
+
+        let change_leaf_index = merkle_tree.insert(change_commitment)?;
+        change_record.commitment = change_commitment;
+        change_record.leaf_index = change_leaf_index;
+        change_record.timestamp = Clock::get()?.unix_timestamp;
+        change_record.is_initialized = true;
+        change_record.bump = ctx.bumps.change_commitment_record;
+
+        pool.anonymity_set_size = pool.anonymity_set_size.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
 
         privacy_payment.total_privacy_payments = privacy_payment.total_privacy_payments.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
 
-        // Reduce commitment amount
-        commitment_record.amount = commitment_record.amount.checked_sub(amount).ok_or(ErrorCode::MathUnderflow)?;
+        let recipient_amount = amount.checked_sub(relayer_fee).ok_or(ErrorCode::MathUnderflow)?;
 
-        // Transfer tokens from vault to recipient
+        // Transfer tokens from the pool to recipient
         let seeds = &[
             b"privacy-payment",
             privacy_payment.token_mint.as_ref(),
@@ -106,83 +251,69 @@ pub mod diap_payment_privacy {
         ];
         let signer_seeds = &[&seeds[..]];
 
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.commitment_vault.to_account_info(),
-            to: ctx.accounts.recipient_token_account.to_account_info(),
-            authority: privacy_payment.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-        token::transfer(cpi_ctx, amount)?;
+        if recipient_amount > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.pool_vault.to_account_info(),
+                to: ctx.accounts.recipient_token_account.to_account_info(),
+                authority: privacy_payment.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, recipient_amount)?;
+        }
+
+        // Pay relayer fee
+        if relayer_fee > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.pool_vault.to_account_info(),
+                to: ctx.accounts.relayer_token_account.to_account_info(),
+                authority: privacy_payment.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, relayer_fee)?;
+        }
 
         emit!(PrivacyPaymentExecutedEvent {
-            commitment,
+            pool: pool.key(),
+            nullifier,
             to,
             amount,
+            change_commitment,
+            change_leaf_index,
+            root: merkle_tree.current_root(),
+            relayer: ctx.accounts.relayer.key(),
+            relayer_fee,
+            encrypted_memo,
         });
 
         Ok(())
     }
 
-    pub fn withdraw_locked_funds(
-        ctx: Context<WithdrawLockedFunds>,
+    /// Return the funds of a commitment locked before deposits moved into pools.
+    /// Those sit in a per-commitment vault that no pool withdrawal can reach.
+    /// Permissionless; the tokens and both accounts' rent always go to the recorded owner.
+    pub fn refund_legacy_commitment(
+        ctx: Context<RefundLegacyCommitment>,
         commitment: [u8; 32],
     ) -> Result<()> {
-        let commitment_record = &mut ctx.accounts.commitment_record;
-
-        require!(commitment_record.is_initialized, ErrorCode::CommitmentNotFound);
-        require!(commitment_record.amount > 0, ErrorCode::NoLockedFunds);
-        require!(!commitment_record.is_used, ErrorCode::CommitmentAlreadyUsed);
-        require!(commitment_record.owner == ctx.accounts.owner.key(), ErrorCode::NotCommitmentOwner);
-
-        let amount = commitment_record.amount;
-        commitment_record.amount = 0;
-
-        // Transfer tokens back to owner
-        let privacy_payment = &ctx.accounts.privacy_payment;
-        let seeds = &[
-            b"privacy-payment",
-            privacy_payment.token_mint.as_ref(),
-            &[privacy_payment.bump],
-        ];
-        let signer_seeds = &[&seeds[..]];
-
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.commitment_vault.to_account_info(),
-            to: ctx.accounts.owner_token_account.to_account_info(),
-            authority: privacy_payment.to_account_info(),
+        let record_info = ctx.accounts.commitment_record.to_account_info();
+        let legacy = {
+            let data = record_info.try_borrow_data()?;
+            require!(
+                data.len() == 8 + LegacyCommitmentRecord::LEN && data[..8] == CommitmentRecord::DISCRIMINATOR,
+                ErrorCode::InvalidLegacyAccount
+            );
+            LegacyCommitmentRecord::deserialize(&mut &data[8..])
+                .map_err(|_| error!(ErrorCode::InvalidLegacyAccount))?
         };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-        token::transfer(cpi_ctx, amount)?;
-
-        emit!(FundsWithdrawnEvent {
-            commitment,
-            amount,
-            withdrawer: ctx.accounts.owner.key(),
-        });
+        require!(legacy.commitment == commitment, ErrorCode::InvalidLegacyAccount);
+        require!(!legacy.is_used, ErrorCode::CommitmentAlreadyUsed);
+        require_keys_eq!(legacy.owner, ctx.accounts.owner.key(), ErrorCode::NotCommitmentOwner);
+        require_keys_eq!(legacy.owner, ctx.accounts.owner_token_account.owner, ErrorCode::NotCommitmentOwner);
 
-        Ok(())
-    }
+        let amount = ctx.accounts.commitment_vault.amount;
 
-    pub fn refund_expired_commitment(
-        ctx: Context<RefundExpiredCommitment>,
-        commitment: [u8; 32],
-    ) -> Result<()> {
-        let commitment_record = &mut ctx.accounts.commitment_record;
-
-        require!(commitment_record.is_initialized, ErrorCode::CommitmentNotFound);
-        require!(commitment_record.amount > 0, ErrorCode::NoLockedFunds);
-        require!(!commitment_record.is_used, ErrorCode::CommitmentAlreadyUsed);
-
-        let clock = Clock::get()?;
-        let expiration_time = commitment_record.timestamp + (90 * 24 * 60 * 60); // 90 days
-        require!(clock.unix_timestamp >= expiration_time, ErrorCode::NotExpiredYet);
-
-        let amount = commitment_record.amount;
-        commitment_record.amount = 0;
-
-        // Transfer tokens back to owner
         let privacy_payment = &ctx.accounts.privacy_payment;
         let seeds = &[
             b"privacy-payment",
@@ -191,19 +322,38 @@ pub mod diap_payment_privacy {
         ];
         let signer_seeds = &[&seeds[..]];
 
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.commitment_vault.to_account_info(),
-            to: ctx.accounts.owner_token_account.to_account_info(),
+        if amount > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.commitment_vault.to_account_info(),
+                to: ctx.accounts.owner_token_account.to_account_info(),
+                authority: privacy_payment.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, amount)?;
+        }
+
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.commitment_vault.to_account_info(),
+            destination: ctx.accounts.owner.to_account_info(),
             authority: privacy_payment.to_account_info(),
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-        token::transfer(cpi_ctx, amount)?;
+        token::close_account(cpi_ctx)?;
+
+        // Close the legacy record by hand, since it cannot be loaded as the current layout
+        let owner_info = ctx.accounts.owner.to_account_info();
+        let lamports = owner_info.lamports().checked_add(record_info.lamports()).ok_or(ErrorCode::MathOverflow)?;
+        **owner_info.try_borrow_mut_lamports()? = lamports;
+        **record_info.try_borrow_mut_lamports()? = 0;
+        record_info.assign(&System::id());
+        record_info.realloc(0, false)?;
 
-        emit!(FundsWithdrawnEvent {
+        emit!(LegacyCommitmentRefundedEvent {
             commitment,
+            owner: legacy.owner,
             amount,
-            withdrawer: commitment_record.owner,
         });
 
         Ok(())
@@ -232,104 +382,155 @@ pub struct Initialize<'info> {
 }
 
 #[derive(Accounts)]
-#[instruction(commitment: [u8; 32], amount: u64)]
-pub struct LockFundsForPrivacy<'info> {
+#[instruction(denomination: u64)]
+pub struct CreateDenominationPool<'info> {
     #[account(
-        mut,
         seeds = [b"privacy-payment", token_mint.key().as_ref()],
-        bump = privacy_payment.bump
+        bump = privacy_payment.bump,
+        has_one = authority
     )]
     pub privacy_payment: Account<'info, PrivacyPayment>,
     
     #[account(
         init,
-        payer = owner,
-        space = 8 + CommitmentRecord::LEN,
-        seeds = [b"commitment", commitment.as_ref()],
+        payer = authority,
+        space = 8 + DenominationPool::LEN,
+        seeds = [b"denomination-pool", privacy_payment.key().as_ref(), denomination.to_le_bytes().as_ref()],
         bump
     )]
-    pub commitment_record: Account<'info, CommitmentRecord>,
+    pub pool: Account<'info, DenominationPool>,
     
     #[account(
-        init_if_needed,
-        payer = owner,
-        token::mint = token_mint,
-        token::authority = privacy_payment,
-        seeds = [b"commitment-vault", commitment.as_ref()],
+        init,
+        payer = authority,
+        space = 8 + MerkleTree::LEN,
+        seeds = [b"merkle-tree", pool.key().as_ref()],
         bump
     )]
-    pub commitment_vault: Account<'info, TokenAccount>,
+    pub merkle_tree: Account<'info, MerkleTree>,
     
     #[account(
-        mut,
+        init,
+        payer = authority,
         token::mint = token_mint,
-        token::authority = owner
+        token::authority = privacy_payment,
+        seeds = [b"pool-vault", pool.key().as_ref()],
+        bump
     )]
-    pub owner_token_account: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub owner: Signer<'info>,
+    pub pool_vault: Account<'info, TokenAccount>,
     
     pub token_mint: Account<'info, Mint>,
     
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(commitment: [u8; 32], nullifier: [u8; 32], amount: u64)]
-pub struct ExecutePrivacyPayment<'info> {
+pub struct SetPoolStatus<'info> {
     #[account(
-        mut,
-        seeds = [b"privacy-payment", token_mint.key().as_ref()],
-        bump = privacy_payment.bump
+        seeds = [b"privacy-payment", privacy_payment.token_mint.as_ref()],
+        bump = privacy_payment.bump,
+        has_one = authority
     )]
     pub privacy_payment: Account<'info, PrivacyPayment>,
     
     #[account(
         mut,
-        seeds = [b"commitment", commitment.as_ref()],
-        bump = commitment_record.bump
+        seeds = [b"denomination-pool", privacy_payment.key().as_ref(), pool.denomination.to_le_bytes().as_ref()],
+        bump = pool.bump
     )]
-    pub commitment_record: Account<'info, CommitmentRecord>,
+    pub pool: Account<'info, DenominationPool>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetVerifyingKey<'info> {
+    #[account(
+        seeds = [b"privacy-payment", privacy_payment.token_mint.as_ref()],
+        bump = privacy_payment.bump,
+        has_one = authority
+    )]
+    pub privacy_payment: Account<'info, PrivacyPayment>,
     
     #[account(
-        init,
-        payer = signer,
-        space = 8 + NullifierRecord::LEN,
-        seeds = [b"nullifier", nullifier.as_ref()],
+        init_if_needed,
+        payer = authority,
+        space = 8 + VerifyingKey::LEN,
+        seeds = [b"verifying-key", privacy_payment.key().as_ref()],
         bump
     )]
-    pub nullifier_record: Account<'info, NullifierRecord>,
+    pub verifying_key: Account<'info, VerifyingKey>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterViewKey<'info> {
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + ViewKeyRecord::LEN,
+        seeds = [b"view-key", owner.key().as_ref()],
+        bump
+    )]
+    pub view_key_record: Account<'info, ViewKeyRecord>,
+    
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(commitment: [u8; 32])]
+pub struct RefundLegacyCommitment<'info> {
+    #[account(
+        seeds = [b"privacy-payment", privacy_payment.token_mint.as_ref()],
+        bump = privacy_payment.bump
+    )]
+    pub privacy_payment: Account<'info, PrivacyPayment>,
     
+    /// CHECK: Pre-upgrade `CommitmentRecord`, decoded by hand since it does not match the current layout
     #[account(
         mut,
-        constraint = commitment_vault.key() == get_commitment_vault_pda(&commitment)
+        seeds = [b"commitment", commitment.as_ref()],
+        bump,
+        owner = crate::ID
     )]
-    pub commitment_vault: Account<'info, TokenAccount>,
+    pub commitment_record: UncheckedAccount<'info>,
     
     #[account(
         mut,
-        token::mint = token_mint,
-        token::authority = recipient
+        seeds = [b"commitment-vault", commitment.as_ref()],
+        bump,
+        token::mint = privacy_payment.token_mint,
+        token::authority = privacy_payment
     )]
-    pub recipient_token_account: Account<'info, TokenAccount>,
+    pub commitment_vault: Account<'info, TokenAccount>,
     
-    /// CHECK: Recipient address
-    pub recipient: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        token::mint = privacy_payment.token_mint
+    )]
+    pub owner_token_account: Account<'info, TokenAccount>,
     
+    /// CHECK: Owner recorded on the legacy commitment, checked in the handler
     #[account(mut)]
-    pub signer: Signer<'info>,
-    
-    pub token_mint: Account<'info, Mint>,
+    pub owner: UncheckedAccount<'info>,
     
     pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(commitment: [u8; 32])]
-pub struct WithdrawLockedFunds<'info> {
+#[instruction(commitment: [u8; 32], amount: u64)]
+pub struct LockFundsForPrivacy<'info> {
     #[account(
         mut,
         seeds = [b"privacy-payment", token_mint.key().as_ref()],
@@ -339,16 +540,32 @@ pub struct WithdrawLockedFunds<'info> {
     
     #[account(
         mut,
+        seeds = [b"denomination-pool", privacy_payment.key().as_ref(), pool.denomination.to_le_bytes().as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, DenominationPool>,
+    
+    #[account(
+        mut,
+        seeds = [b"merkle-tree", pool.key().as_ref()],
+        bump = merkle_tree.bump
+    )]
+    pub merkle_tree: Account<'info, MerkleTree>,
+    
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + CommitmentRecord::LEN,
         seeds = [b"commitment", commitment.as_ref()],
-        bump = commitment_record.bump
+        bump
     )]
     pub commitment_record: Account<'info, CommitmentRecord>,
     
     #[account(
         mut,
-        constraint = commitment_vault.key() == get_commitment_vault_pda(&commitment)
+        constraint = pool_vault.key() == get_pool_vault_pda(&pool.key())
     )]
-    pub commitment_vault: Account<'info, TokenAccount>,
+    pub pool_vault: Account<'info, TokenAccount>,
     
     #[account(
         mut,
@@ -363,11 +580,12 @@ pub struct WithdrawLockedFunds<'info> {
     pub token_mint: Account<'info, Mint>,
     
     pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(commitment: [u8; 32])]
-pub struct RefundExpiredCommitment<'info> {
+#[instruction(root: [u8; 32], nullifier: [u8; 32], change_commitment: [u8; 32])]
+pub struct ExecutePrivacyPayment<'info> {
     #[account(
         mut,
         seeds = [b"privacy-payment", token_mint.key().as_ref()],
@@ -375,32 +593,70 @@ pub struct RefundExpiredCommitment<'info> {
     )]
     pub privacy_payment: Account<'info, PrivacyPayment>,
     
+    #[account(
+        seeds = [b"verifying-key", privacy_payment.key().as_ref()],
+        bump = verifying_key.bump
+    )]
+    pub verifying_key: Account<'info, VerifyingKey>,
+    
     #[account(
         mut,
-        seeds = [b"commitment", commitment.as_ref()],
-        bump = commitment_record.bump
+        seeds = [b"denomination-pool", privacy_payment.key().as_ref(), pool.denomination.to_le_bytes().as_ref()],
+        bump = pool.bump
     )]
-    pub commitment_record: Account<'info, CommitmentRecord>,
+    pub pool: Account<'info, DenominationPool>,
     
     #[account(
         mut,
-        constraint = commitment_vault.key() == get_commitment_vault_pda(&commitment)
+        seeds = [b"merkle-tree", pool.key().as_ref()],
+        bump = merkle_tree.bump
     )]
-    pub commitment_vault: Account<'info, TokenAccount>,
+    pub merkle_tree: Account<'info, MerkleTree>,
+    
+    #[account(
+        init,
+        payer = relayer,
+        space = 8 + NullifierRecord::LEN,
+        seeds = [b"nullifier", nullifier.as_ref()],
+        bump
+    )]
+    pub nullifier_record: Account<'info, NullifierRecord>,
+    
+    #[account(
+        init,
+        payer = relayer,
+        space = 8 + CommitmentRecord::LEN,
+        seeds = [b"commitment", change_commitment.as_ref()],
+        bump
+    )]
+    pub change_commitment_record: Account<'info, CommitmentRecord>,
+    
+    #[account(
+        mut,
+        constraint = pool_vault.key() == get_pool_vault_pda(&pool.key())
+    )]
+    pub pool_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        token::mint = token_mint
+    )]
+    pub recipient_token_account: Account<'info, TokenAccount>,
     
     #[account(
         mut,
         token::mint = token_mint,
-        token::authority = owner
+        token::authority = relayer
     )]
-    pub owner_token_account: Account<'info, TokenAccount>,
+    pub relayer_token_account: Account<'info, TokenAccount>,
     
-    /// CHECK: Can be called by anyone
-    pub owner: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub relayer: Signer<'info>,
     
     pub token_mint: Account<'info, Mint>,
     
     pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
 // ============ State ============
@@ -418,8 +674,38 @@ impl PrivacyPayment {
     pub const LEN: usize = 32 + 32 + 8 + 8 + 1;
 }
 
+/// Fixed denomination deposit pool. Every deposit into a pool has the same
+/// value, so deposits and withdrawals cannot be linked by amount.
+#[account]
+pub struct DenominationPool {
+    pub privacy_payment: Pubkey,
+    pub denomination: u64,
+    pub anonymity_set_size: u64,
+    pub is_active: bool,
+    pub bump: u8,
+}
+
+impl DenominationPool {
+    pub const LEN: usize = 32 + 8 + 8 + 1 + 1;
+}
+
 #[account]
 pub struct CommitmentRecord {
+    pub commitment: [u8; 32],
+    pub leaf_index: u64,
+    pub timestamp: i64,
+    pub bump: u8,
+    pub is_initialized: bool,
+}
+
+impl CommitmentRecord {
+    pub const LEN: usize = 32 + 8 + 8 + 1 + 1;
+}
+
+/// `CommitmentRecord` layout from before the shared pools, when each commitment
+/// held its own funds; read only by `refund_legacy_commitment`
+#[derive(AnchorDeserialize)]
+pub struct LegacyCommitmentRecord {
     pub commitment: [u8; 32],
     pub amount: u64,
     pub owner: Pubkey,
@@ -429,43 +715,189 @@ pub struct CommitmentRecord {
     pub is_initialized: bool,
 }
 
-impl CommitmentRecord {
+impl LegacyCommitmentRecord {
     pub const LEN: usize = 32 + 8 + 32 + 8 + 1 + 1 + 1;
 }
 
 #[account]
 pub struct NullifierRecord {
     pub nullifier: [u8; 32],
-    pub commitment: [u8; 32],
     pub is_used: bool,
     pub bump: u8,
 }
 
 impl NullifierRecord {
-    pub const LEN: usize = 32 + 32 + 1 + 1;
+    pub const LEN: usize = 32 + 1 + 1;
+}
+
+const MERKLE_TREE_DEPTH: usize = 20;
+const ROOT_HISTORY_SIZE: usize = 30;
+
+/// Incremental Poseidon Merkle tree of pool commitments. Only the rightmost
+/// filled node per level is stored, plus a ring buffer of recent roots so
+/// proofs built against a slightly stale root still verify.
+#[account]
+pub struct MerkleTree {
+    pub next_index: u64,
+    pub filled_subtrees: [[u8; 32]; MERKLE_TREE_DEPTH],
+    pub zeros: [[u8; 32]; MERKLE_TREE_DEPTH],
+    pub roots: [[u8; 32]; ROOT_HISTORY_SIZE],
+    pub current_root_index: u8,
+    pub bump: u8,
+}
+
+impl MerkleTree {
+    pub const LEN: usize = 8 + 32 * MERKLE_TREE_DEPTH + 32 * MERKLE_TREE_DEPTH + 32 * ROOT_HISTORY_SIZE + 1 + 1;
+
+    fn init(&mut self, bump: u8) -> Result<()> {
+        let mut zero = [0u8; 32];
+        for level in 0..MERKLE_TREE_DEPTH {
+            self.zeros[level] = zero;
+            self.filled_subtrees[level] = zero;
+            zero = poseidon_hash(&zero, &zero)?;
+        }
+        self.next_index = 0;
+        self.roots = [[0u8; 32]; ROOT_HISTORY_SIZE];
+        self.roots[0] = zero;
+        self.current_root_index = 0;
+        self.bump = bump;
+
+        Ok(())
+    }
+
+    /// Append a leaf and return its index
+    fn insert(&mut self, leaf: [u8; 32]) -> Result<u64> {
+        let leaf_index = self.next_index;
+        require!(leaf_index < (1u64 << MERKLE_TREE_DEPTH), ErrorCode::MerkleTreeFull);
+
+        let mut index = leaf_index;
+        let mut node = leaf;
+        for level in 0..MERKLE_TREE_DEPTH {
+            node = if index & 1 == 0 {
+                self.filled_subtrees[level] = node;
+                poseidon_hash(&node, &self.zeros[level])?
+            } else {
+                poseidon_hash(&self.filled_subtrees[level], &node)?
+            };
+            index /= 2;
+        }
+
+        let root_index = (self.current_root_index as usize + 1) % ROOT_HISTORY_SIZE;
+        self.roots[root_index] = node;
+        self.current_root_index = root_index as u8;
+        self.next_index = leaf_index + 1;
+
+        Ok(leaf_index)
+    }
+
+    fn current_root(&self) -> [u8; 32] {
+        self.roots[self.current_root_index as usize]
+    }
+
+    fn is_known_root(&self, root: &[u8; 32]) -> bool {
+        *root != [0u8; 32] && self.roots.iter().any(|known| known == root)
+    }
+}
+
+/// x25519 viewing key that note memos are encrypted to
+#[account]
+pub struct ViewKeyRecord {
+    pub owner: Pubkey,
+    pub view_key: [u8; 32],
+    pub updated_at: i64,
+    pub bump: u8,
+}
+
+impl ViewKeyRecord {
+    pub const LEN: usize = 32 + 32 + 8 + 1;
+}
+
+/// Memo layout: ephemeral x25519 key (32) || nonce (12) || ChaCha20-Poly1305 ciphertext
+const MAX_MEMO_LEN: usize = 256;
+
+const MAX_PUBLIC_INPUTS: usize = 8;
+
+/// Groth16 verifying key over BN254, points in EIP-197 big-endian encoding
+#[account]
+pub struct VerifyingKey {
+    pub privacy_payment: Pubkey,
+    pub alpha_g1: [u8; 64],
+    pub beta_g2: [u8; 128],
+    pub gamma_g2: [u8; 128],
+    pub delta_g2: [u8; 128],
+    pub ic: Vec<[u8; 64]>,
+    pub bump: u8,
+}
+
+impl VerifyingKey {
+    pub const LEN: usize = 32 + 64 + 128 + 128 + 128 + (4 + (MAX_PUBLIC_INPUTS + 1) * 64) + 1;
+}
+
+/// Groth16 proof, points in EIP-197 big-endian encoding
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Groth16Proof {
+    pub a: [u8; 64],
+    pub b: [u8; 128],
+    pub c: [u8; 64],
 }
 
 // ============ Events ============
 
+#[event]
+pub struct DenominationPoolCreatedEvent {
+    pub pool: Pubkey,
+    pub denomination: u64,
+}
+
+#[event]
+pub struct PoolStatusChangedEvent {
+    pub pool: Pubkey,
+    pub is_active: bool,
+}
+
 #[event]
 pub struct FundsLockedEvent {
+    pub pool: Pubkey,
     pub commitment: [u8; 32],
+    pub leaf: [u8; 32],
+    pub leaf_index: u64,
+    pub root: [u8; 32],
     pub amount: u64,
     pub locker: Pubkey,
+    pub encrypted_memo: Option<Vec<u8>>,
 }
 
 #[event]
 pub struct PrivacyPaymentExecutedEvent {
-    pub commitment: [u8; 32],
+    pub pool: Pubkey,
+    pub nullifier: [u8; 32],
     pub to: Pubkey,
     pub amount: u64,
+    pub change_commitment: [u8; 32],
+    pub change_leaf_index: u64,
+    pub root: [u8; 32],
+    pub relayer: Pubkey,
+    pub relayer_fee: u64,
+    pub encrypted_memo: Option<Vec<u8>>,
 }
 
 #[event]
-pub struct FundsWithdrawnEvent {
+pub struct ViewKeyRegisteredEvent {
+    pub owner: Pubkey,
+    pub view_key: [u8; 32],
+}
+
+#[event]
+pub struct VerifyingKeyUpdatedEvent {
+    pub authority: Pubkey,
+    pub public_inputs: u8,
+}
+
+#[event]
+pub struct LegacyCommitmentRefundedEvent {
     pub commitment: [u8; 32],
+    pub owner: Pubkey,
     pub amount: u64,
-    pub withdrawer: Pubkey,
 }
 
 // ============ Errors ============
@@ -478,30 +910,97 @@ pub enum ErrorCode {
     InvalidCommitment,
     #[msg("Commitment already exists")]
     CommitmentAlreadyExists,
-    #[msg("Commitment not found")]
-    CommitmentNotFound,
-    #[msg("Commitment already used")]
-    CommitmentAlreadyUsed,
     #[msg("Nullifier already used")]
     NullifierAlreadyUsed,
-    #[msg("Insufficient locked funds")]
-    InsufficientLockedFunds,
     #[msg("Invalid privacy proof")]
     InvalidPrivacyProof,
-    #[msg("No locked funds")]
-    NoLockedFunds,
-    #[msg("Not commitment owner")]
-    NotCommitmentOwner,
-    #[msg("Not expired yet")]
-    NotExpiredYet,
+    #[msg("Invalid verifying key")]
+    InvalidVerifyingKey,
+    #[msg("Public input is not a valid field element")]
+    InvalidPublicInput,
     #[msg("Math overflow")]
     MathOverflow,
     #[msg("Math underflow")]
     MathUnderflow,
+    #[msg("Unknown Merkle root")]
+    UnknownMerkleRoot,
+    #[msg("Merkle tree is full")]
+    MerkleTreeFull,
+    #[msg("Poseidon hash failed")]
+    PoseidonHashFailed,
+    #[msg("Amount does not match pool denomination")]
+    InvalidDenomination,
+    #[msg("Pool not active")]
+    PoolNotActive,
+    #[msg("Relayer fee exceeds amount")]
+    RelayerFeeTooHigh,
+    #[msg("Invalid view key")]
+    InvalidViewKey,
+    #[msg("Encrypted memo too long")]
+    MemoTooLong,
+    #[msg("Account is not a valid pre-upgrade account")]
+    InvalidLegacyAccount,
+    #[msg("Commitment already used")]
+    CommitmentAlreadyUsed,
+    #[msg("Not commitment owner")]
+    NotCommitmentOwner,
 }
 
 // ============ Utilities ============
 
-fn get_commitment_vault_pda(commitment: &[u8; 32]) -> Pubkey {
-    Pubkey::find_program_address(&[b"commitment-vault", commitment.as_ref()], &ID).0
+fn get_pool_vault_pda(pool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"pool-vault", pool.as_ref()], &ID).0
+}
+
+fn check_memo(encrypted_memo: &Option<Vec<u8>>) -> Result<()> {
+    if let Some(memo) = encrypted_memo {
+        require!(memo.len() <= MAX_MEMO_LEN, ErrorCode::MemoTooLong);
+    }
+    Ok(())
+}
+
+fn poseidon_hash(left: &[u8; 32], right: &[u8; 32]) -> Result<[u8; 32]> {
+    poseidon::hashv(Parameters::Bn254X5, Endianness::BigEndian, &[left, right])
+        .map(|hash| hash.to_bytes())
+        .map_err(|_| error!(ErrorCode::PoseidonHashFailed))
+}
+
+/// Map a public key into the scalar field. The hash is truncated to 248 bits
+/// so the result is always below the modulus.
+pub fn pubkey_to_field(key: &Pubkey) -> [u8; 32] {
+    let mut field = hashv(&[key.as_ref()]).to_bytes();
+    field[0] = 0;
+    field
+}
+
+pub fn u64_to_field(value: u64) -> [u8; 32] {
+    let mut field = [0u8; 32];
+    field[24..].copy_from_slice(&value.to_be_bytes());
+    field
+}
+
+/// Check a Groth16 proof against the pool verifying key
+fn verify_groth16_proof(
+    verifying_key: &VerifyingKey,
+    proof: &Groth16Proof,
+    public_inputs: &[[u8; 32]],
+) -> Result<()> {
+    let points = Groth16VerifyingKey {
+        alpha_g1: &verifying_key.alpha_g1,
+        beta_g2: &verifying_key.beta_g2,
+        gamma_g2: &verifying_key.gamma_g2,
+        delta_g2: &verifying_key.delta_g2,
+        ic: &verifying_key.ic,
+    };
+    let proof = Groth16ProofPoints {
+        a: &proof.a,
+        b: &proof.b,
+        c: &proof.c,
+    };
+
+    diap_crypto::verify_groth16_proof(&points, &proof, public_inputs).map_err(|error| match error {
+        CryptoError::InvalidVerifyingKey => error!(ErrorCode::InvalidVerifyingKey),
+        CryptoError::InvalidPublicInput => error!(ErrorCode::InvalidPublicInput),
+        _ => error!(ErrorCode::InvalidPrivacyProof),
+    })
 }
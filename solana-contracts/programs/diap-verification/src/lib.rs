@@ -3,7 +3,16 @@
 //! Identity and reputation verification using ZKPs.
 //! Adapted from Solidity DIAPVerification.sol
 
+#![allow(unexpected_cfgs)]
+
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use diap_crypto::{
+    load_ed25519_signatures, CryptoError, Groth16ProofPoints, Groth16VerifyingKey, BN254_SCALAR_MODULUS,
+};
 
 declare_id!("7xKXtg2CW87d97TXJSDpbD5jBkheTqA83TZRuJosgAsU");
 
@@ -26,35 +35,54 @@ pub mod diap_verification {
         verification.total_verifications = 0;
         verification.total_successful_verifications = 0;
         verification.total_failed_verifications = 0;
-        verification.zkp_verifier = None;
         verification.verification_mode = VerificationMode::Hybrid as u8;
+        verification.reviewers = [Pubkey::default(); MAX_REVIEWERS];
+        verification.num_reviewers = 0;
+        verification.required_approvals = 1;
+        verification.reporters = [Pubkey::default(); MAX_REPORTERS];
+        verification.num_reporters = 0;
+        verification.moderators = [Pubkey::default(); MAX_MODERATORS];
+        verification.num_moderators = 0;
+        verification.required_confirmations = 1;
+        verification.report_bond = 0;
+        verification.total_reports = 0;
+        verification.blacklist_durations = DEFAULT_BLACKLIST_DURATIONS;
+        verification.appeal_stake = 0;
+        verification.attempt_cooldown = DEFAULT_ATTEMPT_COOLDOWN;
         verification.bump = ctx.bumps.verification;
 
         Ok(())
     }
 
+    /// Open a verification session for the caller's `did:diap` identifier. The
+    /// caller's `DidDocument` must exist; its DID is what the proof is bound to.
     pub fn initiate_identity_verification(
         ctx: Context<InitiateIdentityVerification>,
-        did_document: String,
         public_key: String,
         commitment: [u8; 32],
         nullifier: [u8; 32],
-        proof: [u8; 8],
+        proof: Groth16Proof,
     ) -> Result<()> {
-        require!(did_document.len() > 0 && did_document.len() <= 1000, ErrorCode::InvalidDIDDocumentLength);
-        require!(public_key.len() > 0 && public_key.len() <= 1000, ErrorCode::InvalidPublicKeyLength);
-        require!(commitment != [0u8; 32], ErrorCode::InvalidCommitment);
-        require!(nullifier != [0u8; 32], ErrorCode::InvalidNullifier);
+        require!(!public_key.is_empty() && public_key.len() <= 1000, ErrorCode::InvalidPublicKeyLength);
+        require!(commitment != [0u8; 32] && commitment < BN254_SCALAR_MODULUS, ErrorCode::InvalidCommitment);
+        require!(nullifier != [0u8; 32] && nullifier < BN254_SCALAR_MODULUS, ErrorCode::InvalidNullifier);
 
         let verification = &mut ctx.accounts.verification;
         let session = &mut ctx.accounts.session;
-        let agent = &ctx.accounts.agent;
+        let agent = &mut ctx.accounts.agent;
+        let clock = Clock::get()?;
 
-        require!(!agent.is_blacklisted, ErrorCode::AgentIsBlacklisted);
-        require!(!ctx.accounts.nullifier_record.is_used, ErrorCode::NullifierAlreadyUsed);
+        decay_failed_attempts(agent, clock.unix_timestamp, verification.attempt_cooldown);
+        require!(!agent.is_blacklisted_at(clock.unix_timestamp), ErrorCode::AgentIsBlacklisted);
         require!(agent.failed_attempts < verification.max_verification_attempts, ErrorCode::TooManyFailedAttempts);
 
-        let clock = Clock::get()?;
+        // The nullifier record is created here, so a nullifier opens at most one session
+        let nullifier_record = &mut ctx.accounts.nullifier_record;
+        nullifier_record.nullifier = nullifier;
+        nullifier_record.is_used = true;
+        nullifier_record.bump = ctx.bumps.nullifier_record;
+
+        let did_document = format!("did:diap:{}", ctx.accounts.did_document.agent);
         let session_id = generate_session_id(
             ctx.accounts.signer.key(),
             &did_document,
@@ -74,6 +102,9 @@ pub mod diap_verification {
         session.status = VerificationStatus::Pending as u8;
         session.proof = proof;
         session.is_valid = false;
+        session.approvers = [Pubkey::default(); MAX_REVIEWERS];
+        session.num_approvals = 0;
+        session.rejection_reason = 0;
         session.bump = ctx.bumps.session;
 
         verification.total_verifications = verification.total_verifications.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
@@ -87,17 +118,24 @@ pub mod diap_verification {
         Ok(())
     }
 
+    /// Check the session's Groth16 proof. Anyone may call this since the proof
+    /// decides the outcome. In `ZkpAutomated` mode a valid proof writes the
+    /// `IdentityProof` straight away; in `Hybrid` mode the session moves on to
+    /// reviewer approval. `OwnerManual` sessions go through `approve_session` only.
     pub fn verify_identity(ctx: Context<VerifyIdentity>) -> Result<bool> {
         let session = &mut ctx.accounts.session;
         let agent = &mut ctx.accounts.agent;
+        let mode = ctx.accounts.verification.verification_mode;
+
+        require!(mode != VerificationMode::OwnerManual as u8, ErrorCode::ManualReviewRequired);
 
         // Verify session is still pending and not expired
         require!(session.status == VerificationStatus::Pending as u8, ErrorCode::SessionNotPending);
         
-        // Double-check agent is not blacklisted (race condition protection)
-        require!(!agent.is_blacklisted, ErrorCode::AgentIsBlacklisted);
-
         let clock = Clock::get()?;
+
+        // Double-check agent is not blacklisted (race condition protection)
+        require!(!agent.is_blacklisted_at(clock.unix_timestamp), ErrorCode::AgentIsBlacklisted);
         let verification_timeout = ctx.accounts.verification.verification_timeout;
         let expiration_time = session.timestamp + verification_timeout;
         
@@ -108,42 +146,33 @@ pub mod diap_verification {
         require!(clock.unix_timestamp >= session.timestamp, ErrorCode::SessionExpired);
 
         // Verify ZKP proof
-        let is_valid = verify_zkp_proof(session.proof, &session.did_document, &session.public_key);
+        let public_inputs = identity_public_inputs(session);
+        let is_valid = verify_groth16_proof(&ctx.accounts.verifying_key, &session.proof, &public_inputs)?;
 
-        if is_valid {
-            session.status = VerificationStatus::Verified as u8;
+        if is_valid && mode == VerificationMode::Hybrid as u8 {
+            session.status = VerificationStatus::AwaitingReview as u8;
             session.is_valid = true;
-            
-            // Update verification stats - use reborrow to avoid conflict
-            let verification = &mut *ctx.accounts.verification;
-            verification.total_successful_verifications = verification.total_successful_verifications.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
-
-            // Store identity proof
-            let identity_proof = &mut ctx.accounts.identity_proof;
-            identity_proof.agent = session.agent;
-            identity_proof.did_document = session.did_document.clone();
-            identity_proof.public_key = session.public_key.clone();
-            identity_proof.commitment = session.commitment;
-            identity_proof.nullifier = session.nullifier;
-            identity_proof.proof = session.proof;
-            identity_proof.timestamp = clock.unix_timestamp;
-            identity_proof.is_verified = true;
-            identity_proof.bump = ctx.bumps.identity_proof;
-
-            // Reset failed attempts
-            agent.failed_attempts = 0;
 
-            emit!(VerificationCompletedEvent {
+            emit!(SessionAwaitingReviewEvent {
                 session_id: session.session_id,
                 agent: session.agent,
-                is_valid: true,
-            });
-
-            emit!(IdentityVerifiedEvent {
-                agent: session.agent,
-                did_document: session.did_document.clone(),
-                timestamp: clock.unix_timestamp,
+                required_approvals: ctx.accounts.verification.required_approvals,
             });
+        } else if is_valid {
+            let identity_proof = ctx
+                .accounts
+                .identity_proof
+                .as_mut()
+                .ok_or(ErrorCode::IdentityProofAccountRequired)?;
+            let identity_proof_bump = ctx.bumps.identity_proof.ok_or(ErrorCode::IdentityProofAccountRequired)?;
+            complete_verification(
+                &mut ctx.accounts.verification,
+                session,
+                agent,
+                identity_proof,
+                identity_proof_bump,
+                clock.unix_timestamp,
+            )?;
         } else {
             session.status = VerificationStatus::Failed as u8;
             session.is_valid = false;
@@ -152,22 +181,12 @@ pub mod diap_verification {
             let verification = &mut *ctx.accounts.verification;
             verification.total_failed_verifications = verification.total_failed_verifications.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
 
-            // Increment failed attempts
+            // Increment failed attempts; reaching `max_verification_attempts` locks the
+            // agent out of new sessions until the count decays
+            decay_failed_attempts(agent, clock.unix_timestamp, verification.attempt_cooldown);
             agent.failed_attempts = agent.failed_attempts.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
             agent.last_failed_attempt = clock.unix_timestamp;
 
-            // Check if should blacklist
-            let max_attempts = ctx.accounts.verification.max_verification_attempts;
-            if agent.failed_attempts >= max_attempts {
-                agent.is_blacklisted = true;
-                
-                emit!(AgentBlacklistedEvent {
-                    agent: session.agent,
-                    reason: "Too many failed verification attempts".to_string(),
-                    timestamp: clock.unix_timestamp,
-                });
-            }
-
             emit!(VerificationCompletedEvent {
                 session_id: session.session_id,
                 agent: session.agent,
@@ -178,6 +197,162 @@ pub mod diap_verification {
         Ok(is_valid)
     }
 
+    /// Record a reviewer approval. `OwnerManual` sessions are reviewed while
+    /// pending; `Hybrid` sessions only after their proof has been verified. The
+    /// `IdentityProof` is written once `required_approvals` is reached.
+    pub fn approve_session(ctx: Context<ReviewSession>) -> Result<()> {
+        let verification = &mut ctx.accounts.verification;
+        let session = &mut ctx.accounts.session;
+        let agent = &mut ctx.accounts.agent;
+        let reviewer = ctx.accounts.reviewer.key();
+
+        require!(is_reviewer(verification, &reviewer), ErrorCode::NotReviewer);
+
+        let clock = Clock::get()?;
+        require!(!agent.is_blacklisted_at(clock.unix_timestamp), ErrorCode::AgentIsBlacklisted);
+        let expiration_time = session.timestamp
+            .checked_add(verification.verification_timeout)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(clock.unix_timestamp <= expiration_time, ErrorCode::SessionExpired);
+
+        let expected_status = match verification.verification_mode {
+            mode if mode == VerificationMode::OwnerManual as u8 => VerificationStatus::Pending,
+            mode if mode == VerificationMode::Hybrid as u8 => VerificationStatus::AwaitingReview,
+            _ => return Err(ErrorCode::ReviewNotRequired.into()),
+        };
+        require!(session.status == expected_status as u8, ErrorCode::SessionNotPending);
+
+        // Approvals from reviewers removed since they approved no longer count
+        let mut approvers: Vec<Pubkey> = session.approvers[..session.num_approvals as usize]
+            .iter()
+            .filter(|approver| is_reviewer(verification, approver))
+            .copied()
+            .collect();
+        require!(!approvers.contains(&reviewer), ErrorCode::AlreadyApproved);
+        approvers.push(reviewer);
+        require!(approvers.len() <= MAX_REVIEWERS, ErrorCode::MaxReviewersReached);
+
+        session.approvers = [Pubkey::default(); MAX_REVIEWERS];
+        session.approvers[..approvers.len()].copy_from_slice(&approvers);
+        session.num_approvals = approvers.len() as u8;
+
+        emit!(SessionApprovedEvent {
+            session_id: session.session_id,
+            reviewer,
+            approvals: session.num_approvals,
+        });
+
+        if session.num_approvals >= verification.required_approvals {
+            let identity_proof = ctx
+                .accounts
+                .identity_proof
+                .as_mut()
+                .ok_or(ErrorCode::IdentityProofAccountRequired)?;
+            let identity_proof_bump = ctx.bumps.identity_proof.ok_or(ErrorCode::IdentityProofAccountRequired)?;
+            complete_verification(
+                verification,
+                session,
+                agent,
+                identity_proof,
+                identity_proof_bump,
+                clock.unix_timestamp,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Reject a session under review with a `RejectionReason` code
+    pub fn reject_session(ctx: Context<RejectSession>, reason: u8) -> Result<()> {
+        require!(reason <= RejectionReason::Other as u8, ErrorCode::InvalidRejectionReason);
+
+        let verification = &mut ctx.accounts.verification;
+        let session = &mut ctx.accounts.session;
+        let reviewer = ctx.accounts.reviewer.key();
+
+        require!(is_reviewer(verification, &reviewer), ErrorCode::NotReviewer);
+        require!(
+            session.status == VerificationStatus::Pending as u8
+                || session.status == VerificationStatus::AwaitingReview as u8,
+            ErrorCode::SessionNotPending
+        );
+
+        session.status = VerificationStatus::Rejected as u8;
+        session.is_valid = false;
+        session.rejection_reason = reason;
+
+        verification.total_failed_verifications = verification.total_failed_verifications.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(SessionRejectedEvent {
+            session_id: session.session_id,
+            agent: session.agent,
+            reviewer,
+            reason,
+        });
+
+        emit!(VerificationCompletedEvent {
+            session_id: session.session_id,
+            agent: session.agent,
+            is_valid: false,
+        });
+
+        Ok(())
+    }
+
+    pub fn add_reviewer(ctx: Context<UpdateConfig>, reviewer: Pubkey) -> Result<()> {
+        let verification = &mut ctx.accounts.verification;
+        require!((verification.num_reviewers as usize) < MAX_REVIEWERS, ErrorCode::MaxReviewersReached);
+        require!(!is_reviewer(verification, &reviewer), ErrorCode::ReviewerAlreadyExists);
+
+        let idx = verification.num_reviewers as usize;
+        verification.reviewers[idx] = reviewer;
+        verification.num_reviewers = verification.num_reviewers.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(ReviewerAddedEvent {
+            reviewer,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_reviewer(ctx: Context<UpdateConfig>, reviewer: Pubkey) -> Result<()> {
+        let verification = &mut ctx.accounts.verification;
+        let count = verification.num_reviewers as usize;
+        let idx = verification.reviewers[..count]
+            .iter()
+            .position(|r| *r == reviewer)
+            .ok_or(ErrorCode::NotReviewer)?;
+
+        // Swap-remove to keep the active reviewers contiguous
+        verification.reviewers[idx] = verification.reviewers[count - 1];
+        verification.reviewers[count - 1] = Pubkey::default();
+        verification.num_reviewers -= 1;
+
+        emit!(ReviewerRemovedEvent {
+            reviewer,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_required_approvals(ctx: Context<UpdateConfig>, required_approvals: u8) -> Result<()> {
+        require!(
+            required_approvals > 0 && (required_approvals as usize) <= MAX_REVIEWERS,
+            ErrorCode::InvalidRequiredApprovals
+        );
+
+        let verification = &mut ctx.accounts.verification;
+        let old_required_approvals = verification.required_approvals;
+        verification.required_approvals = required_approvals;
+
+        emit!(RequiredApprovalsUpdatedEvent {
+            old_required_approvals,
+            new_required_approvals: required_approvals,
+        });
+
+        Ok(())
+    }
+
     pub fn verify_reputation(
         ctx: Context<VerifyReputation>,
         agent: Pubkey,
@@ -187,7 +362,7 @@ pub mod diap_verification {
         require!(reputation <= 10000, ErrorCode::InvalidReputationScore);
 
         let agent_record = &ctx.accounts.agent_record;
-        require!(!agent_record.is_blacklisted, ErrorCode::AgentIsBlacklisted);
+        require!(!agent_record.is_blacklisted_at(Clock::get()?.unix_timestamp), ErrorCode::AgentIsBlacklisted);
 
         // Verify reputation proof
         let is_valid = verify_reputation_proof(proof, agent, reputation);
@@ -213,124 +388,1070 @@ pub mod diap_verification {
         Ok(is_valid)
     }
 
+    /// Register a credential issuer key (authority only)
+    pub fn register_issuer(ctx: Context<RegisterIssuer>, issuer: Pubkey, name: String) -> Result<()> {
+        require!(!name.is_empty() && name.len() <= 64, ErrorCode::InvalidIssuerName);
+
+        let issuer_record = &mut ctx.accounts.issuer_record;
+        issuer_record.issuer = issuer;
+        issuer_record.name = name.clone();
+        issuer_record.is_active = true;
+        issuer_record.registered_at = Clock::get()?.unix_timestamp;
+        issuer_record.bump = ctx.bumps.issuer_record;
+
+        emit!(IssuerRegisteredEvent {
+            issuer,
+            name,
+        });
+
+        Ok(())
+    }
+
+    /// Enable or disable a registered issuer (authority only). Attestations from
+    /// a disabled issuer stop being accepted by `holds_credential`.
+    pub fn set_issuer_status(ctx: Context<SetIssuerStatus>, is_active: bool) -> Result<()> {
+        let issuer_record = &mut ctx.accounts.issuer_record;
+        issuer_record.is_active = is_active;
+
+        emit!(IssuerStatusUpdatedEvent {
+            issuer: issuer_record.issuer,
+            is_active,
+        });
+
+        Ok(())
+    }
+
+    /// Store a credential attestation. The issuer's Ed25519 signature over
+    /// `attestation_message` must be verified by the preceding instruction, so
+    /// the subject or anyone else can submit it. Re-issuing replaces the claims;
+    /// the signed version number stops an old signature from being replayed.
+    pub fn issue_attestation(
+        ctx: Context<IssueAttestation>,
+        schema_id: [u8; 32],
+        subject: Pubkey,
+        claims_hash: [u8; 32],
+        expires_at: i64,
+    ) -> Result<()> {
+        let issuer_record = &ctx.accounts.issuer_record;
+        require!(issuer_record.is_active, ErrorCode::IssuerNotActive);
+
+        let clock = Clock::get()?;
+        require!(expires_at == 0 || expires_at > clock.unix_timestamp, ErrorCode::InvalidExpiry);
+
+        let attestation = &mut ctx.accounts.attestation;
+        let version = attestation.version;
+
+        let message = attestation_message(&issuer_record.issuer, &schema_id, &subject, &claims_hash, expires_at, version);
+        let signature = find_ed25519_signature(&ctx.accounts.instructions, &issuer_record.issuer, &message)?;
+
+        attestation.issuer = issuer_record.issuer;
+        attestation.subject = subject;
+        attestation.schema_id = schema_id;
+        attestation.claims_hash = claims_hash;
+        attestation.issued_at = clock.unix_timestamp;
+        attestation.expires_at = expires_at;
+        attestation.signature = signature;
+        attestation.is_revoked = false;
+        attestation.revoked_at = 0;
+        attestation.version = version.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        attestation.bump = ctx.bumps.attestation;
+
+        emit!(AttestationIssuedEvent {
+            issuer: attestation.issuer,
+            subject,
+            schema_id,
+            claims_hash,
+            expires_at,
+        });
+
+        Ok(())
+    }
+
+    /// Revoke an attestation (issuing key only)
+    pub fn revoke_attestation(ctx: Context<RevokeAttestation>) -> Result<()> {
+        let attestation = &mut ctx.accounts.attestation;
+        require!(!attestation.is_revoked, ErrorCode::AttestationRevoked);
+
+        let clock = Clock::get()?;
+        attestation.is_revoked = true;
+        attestation.revoked_at = clock.unix_timestamp;
+        // Invalidate any signed but unsubmitted re-issue
+        attestation.version = attestation.version.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(AttestationRevokedEvent {
+            issuer: attestation.issuer,
+            subject: attestation.subject,
+            schema_id: attestation.schema_id,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Create the `did:diap:<agent>` document. The agent key becomes the first
+    /// controller and the first verification method (`#key-1`).
+    pub fn create_did_document(ctx: Context<CreateDidDocument>) -> Result<()> {
+        let agent = ctx.accounts.agent.key();
+        let clock = Clock::get()?;
+
+        let did_document = &mut ctx.accounts.did_document;
+        did_document.agent = agent;
+        did_document.controllers = vec![agent];
+        did_document.verification_methods = vec![VerificationMethod {
+            id: "key-1".to_string(),
+            key_type: KeyType::Ed25519 as u8,
+            public_key: agent.to_bytes(),
+            relationships: RELATIONSHIP_AUTHENTICATION
+                | RELATIONSHIP_ASSERTION_METHOD
+                | RELATIONSHIP_CAPABILITY_INVOCATION,
+        }];
+        did_document.services = Vec::new();
+        did_document.created_at = clock.unix_timestamp;
+        did_document.updated_at = clock.unix_timestamp;
+        did_document.bump = ctx.bumps.did_document;
+
+        emit!(DidDocumentCreatedEvent {
+            agent,
+        });
+
+        Ok(())
+    }
+
+    pub fn add_verification_method(
+        ctx: Context<UpdateDidDocument>,
+        id: String,
+        key_type: u8,
+        public_key: [u8; 32],
+        relationships: u8,
+    ) -> Result<()> {
+        require!(is_valid_fragment(&id), ErrorCode::InvalidDidFragment);
+        require!(key_type <= KeyType::X25519 as u8, ErrorCode::InvalidKeyType);
+        require!(
+            relationships != 0 && relationships & !RELATIONSHIP_ALL == 0,
+            ErrorCode::InvalidRelationships
+        );
+        // Key agreement keys cannot sign, and signing keys are not used for key agreement
+        if key_type == KeyType::X25519 as u8 {
+            require!(relationships == RELATIONSHIP_KEY_AGREEMENT, ErrorCode::InvalidRelationships);
+        } else {
+            require!(relationships & RELATIONSHIP_KEY_AGREEMENT == 0, ErrorCode::InvalidRelationships);
+        }
+
+        let did_document = &mut ctx.accounts.did_document;
+        require!(
+            did_document.verification_methods.len() < MAX_VERIFICATION_METHODS,
+            ErrorCode::TooManyVerificationMethods
+        );
+        require!(
+            !did_document.verification_methods.iter().any(|m| m.id == id),
+            ErrorCode::DuplicateDidFragment
+        );
+
+        did_document.verification_methods.push(VerificationMethod {
+            id: id.clone(),
+            key_type,
+            public_key,
+            relationships,
+        });
+        did_document.updated_at = Clock::get()?.unix_timestamp;
+
+        emit!(DidDocumentUpdatedEvent {
+            agent: did_document.agent,
+            change: DidChange::MethodAdded as u8,
+            id,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_verification_method(ctx: Context<UpdateDidDocument>, id: String) -> Result<()> {
+        let did_document = &mut ctx.accounts.did_document;
+        let idx = did_document
+            .verification_methods
+            .iter()
+            .position(|m| m.id == id)
+            .ok_or(ErrorCode::DidFragmentNotFound)?;
+        did_document.verification_methods.remove(idx);
+        did_document.updated_at = Clock::get()?.unix_timestamp;
+
+        emit!(DidDocumentUpdatedEvent {
+            agent: did_document.agent,
+            change: DidChange::MethodRemoved as u8,
+            id,
+        });
+
+        Ok(())
+    }
+
+    /// Replace the key material of a verification method, keeping its id and relationships
+    pub fn rotate_verification_method(
+        ctx: Context<UpdateDidDocument>,
+        id: String,
+        new_public_key: [u8; 32],
+    ) -> Result<()> {
+        let did_document = &mut ctx.accounts.did_document;
+        let method = did_document
+            .verification_methods
+            .iter_mut()
+            .find(|m| m.id == id)
+            .ok_or(ErrorCode::DidFragmentNotFound)?;
+        require!(method.public_key != new_public_key, ErrorCode::InvalidKeyRotation);
+        method.public_key = new_public_key;
+        did_document.updated_at = Clock::get()?.unix_timestamp;
+
+        emit!(DidDocumentUpdatedEvent {
+            agent: did_document.agent,
+            change: DidChange::MethodRotated as u8,
+            id,
+        });
+
+        Ok(())
+    }
+
+    pub fn add_service(
+        ctx: Context<UpdateDidDocument>,
+        id: String,
+        service_type: String,
+        endpoint: String,
+    ) -> Result<()> {
+        require!(is_valid_fragment(&id), ErrorCode::InvalidDidFragment);
+        require!(
+            !service_type.is_empty() && service_type.len() <= MAX_SERVICE_TYPE_LEN,
+            ErrorCode::InvalidServiceType
+        );
+        require!(
+            !endpoint.is_empty() && endpoint.len() <= MAX_SERVICE_ENDPOINT_LEN,
+            ErrorCode::InvalidServiceEndpoint
+        );
+
+        let did_document = &mut ctx.accounts.did_document;
+        require!(did_document.services.len() < MAX_SERVICES, ErrorCode::TooManyServices);
+        require!(
+            !did_document.services.iter().any(|s| s.id == id),
+            ErrorCode::DuplicateDidFragment
+        );
+
+        did_document.services.push(ServiceEndpoint {
+            id: id.clone(),
+            service_type,
+            endpoint,
+        });
+        did_document.updated_at = Clock::get()?.unix_timestamp;
+
+        emit!(DidDocumentUpdatedEvent {
+            agent: did_document.agent,
+            change: DidChange::ServiceAdded as u8,
+            id,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_service(ctx: Context<UpdateDidDocument>, id: String) -> Result<()> {
+        let did_document = &mut ctx.accounts.did_document;
+        let idx = did_document
+            .services
+            .iter()
+            .position(|s| s.id == id)
+            .ok_or(ErrorCode::DidFragmentNotFound)?;
+        did_document.services.remove(idx);
+        did_document.updated_at = Clock::get()?.unix_timestamp;
+
+        emit!(DidDocumentUpdatedEvent {
+            agent: did_document.agent,
+            change: DidChange::ServiceRemoved as u8,
+            id,
+        });
+
+        Ok(())
+    }
+
+    pub fn add_did_controller(ctx: Context<UpdateDidDocument>, controller: Pubkey) -> Result<()> {
+        let did_document = &mut ctx.accounts.did_document;
+        require!(did_document.controllers.len() < MAX_DID_CONTROLLERS, ErrorCode::TooManyControllers);
+        require!(!did_document.controllers.contains(&controller), ErrorCode::DuplicateController);
+
+        did_document.controllers.push(controller);
+        did_document.updated_at = Clock::get()?.unix_timestamp;
+
+        emit!(DidDocumentUpdatedEvent {
+            agent: did_document.agent,
+            change: DidChange::ControllerAdded as u8,
+            id: controller.to_string(),
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_did_controller(ctx: Context<UpdateDidDocument>, controller: Pubkey) -> Result<()> {
+        let did_document = &mut ctx.accounts.did_document;
+        let idx = did_document
+            .controllers
+            .iter()
+            .position(|c| *c == controller)
+            .ok_or(ErrorCode::NotDidController)?;
+        require!(did_document.controllers.len() > 1, ErrorCode::LastController);
+
+        did_document.controllers.remove(idx);
+        did_document.updated_at = Clock::get()?.unix_timestamp;
+
+        emit!(DidDocumentUpdatedEvent {
+            agent: did_document.agent,
+            change: DidChange::ControllerRemoved as u8,
+            id: controller.to_string(),
+        });
+
+        Ok(())
+    }
+
+    /// File a report against an agent (reporters only). The reporter posts
+    /// `report_bond` lamports, returned if moderators confirm the report and
+    /// paid to the reported agent if they dismiss it.
     pub fn detect_malicious_behavior(
         ctx: Context<DetectMaliciousBehavior>,
         agent: Pubkey,
         behavior_type: String,
+        severity: u8,
+        evidence_cid: String,
     ) -> Result<()> {
+        let behavior_type = parse_behavior_type(&behavior_type)?;
+        require!((severity as usize) < NUM_SEVERITIES, ErrorCode::InvalidSeverity);
+        require!(
+            !evidence_cid.is_empty() && evidence_cid.len() <= MAX_EVIDENCE_CID_LEN,
+            ErrorCode::InvalidEvidenceCid
+        );
+
+        let reporter = ctx.accounts.reporter.key();
+        let verification = &mut ctx.accounts.verification;
+        require!(is_reporter(verification, &reporter), ErrorCode::NotReporter);
+
+        // Bond is held as lamports on the report account until it is resolved
+        let bond = verification.report_bond;
+        if bond > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.reporter.to_account_info(),
+                        to: ctx.accounts.report.to_account_info(),
+                    },
+                ),
+                bond,
+            )?;
+        }
+
+        let clock = Clock::get()?;
+        let report = &mut ctx.accounts.report;
+        report.report_id = verification.total_reports;
+        report.agent = agent;
+        report.reporter = reporter;
+        report.behavior_type = behavior_type;
+        report.severity = severity;
+        report.evidence_cid = evidence_cid.clone();
+        report.bond = bond;
+        report.status = ReportStatus::Pending as u8;
+        report.voters = [Pubkey::default(); MAX_MODERATORS];
+        report.confirms = [false; MAX_MODERATORS];
+        report.num_votes = 0;
+        report.confirmations = 0;
+        report.dismissals = 0;
+        report.created_at = clock.unix_timestamp;
+        report.resolved_at = 0;
+        report.bump = ctx.bumps.report;
+
+        verification.total_reports = verification.total_reports.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
         let agent_record = &mut ctx.accounts.agent_record;
-        require!(!agent_record.is_blacklisted, ErrorCode::AgentAlreadyBlacklisted);
-        
-        // Validate behavior type length to prevent abuse
-        require!(behavior_type.len() <= 50, ErrorCode::InvalidBehaviorType);
-
-        match behavior_type.as_str() {
-            "SPAM" | "FRAUD" | "ATTACK" | "ABUSE" | "MISBEHAVIOR" => {
-                agent_record.is_blacklisted = true;
-                
-                let clock = Clock::get()?;
-                emit!(AgentBlacklistedEvent {
-                    agent,
-                    reason: behavior_type.clone(),
-                    timestamp: clock.unix_timestamp,
-                });
-            },
-            _ => {
-                return Err(ErrorCode::InvalidBehaviorType.into());
-            }
+        if agent_record.agent == Pubkey::default() {
+            agent_record.agent = agent;
+            agent_record.bump = ctx.bumps.agent_record;
         }
+        agent_record.pending_reports = agent_record.pending_reports.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        agent_record.last_report_at = clock.unix_timestamp;
+
+        emit!(ReportSubmittedEvent {
+            report: report.key(),
+            agent,
+            reporter,
+            behavior_type,
+            severity,
+            evidence_cid,
+            bond,
+        });
 
         Ok(())
     }
 
-    pub fn remove_from_blacklist(ctx: Context<RemoveFromBlacklist>) -> Result<()> {
+    /// Confirm a pending report (moderators only). Once `required_confirmations`
+    /// moderators agree the agent is blacklisted and the bond is returned.
+    pub fn confirm_report(ctx: Context<ModerateReport>) -> Result<()> {
+        let moderator = ctx.accounts.moderator.key();
+        let verification = &ctx.accounts.verification;
+        let report = &mut ctx.accounts.report;
+
+        let clock = Clock::get()?;
+        record_report_vote(verification, report, &moderator, true, clock.unix_timestamp)?;
+
+        emit!(ReportVoteCastEvent {
+            report: report.key(),
+            moderator,
+            confirm: true,
+        });
+
+        if report.confirmations < verification.required_confirmations {
+            return Ok(());
+        }
+
+        report.status = ReportStatus::Confirmed as u8;
+        report.resolved_at = clock.unix_timestamp;
+
         let agent_record = &mut ctx.accounts.agent_record;
-        require!(agent_record.is_blacklisted, ErrorCode::AgentNotBlacklisted);
+        if agent_record.agent == Pubkey::default() {
+            agent_record.agent = report.agent;
+            agent_record.bump = ctx.bumps.agent_record;
+        }
+        release_pending_report(agent_record);
+        apply_blacklist(
+            agent_record,
+            report.severity,
+            clock.unix_timestamp,
+            verification.blacklist_durations[report.severity as usize],
+        )?;
+        let expires_at = agent_record.blacklist_expires_at;
+
+        let bond = report.bond;
+        pay_out_bond(
+            &ctx.accounts.report.to_account_info(),
+            &ctx.accounts.reporter.to_account_info(),
+            bond,
+        )?;
+
+        let report = &ctx.accounts.report;
+        emit!(AgentBlacklistedEvent {
+            agent: report.agent,
+            reason: behavior_type_label(report.behavior_type).to_string(),
+            severity: report.severity,
+            expires_at,
+            timestamp: clock.unix_timestamp,
+        });
 
-        agent_record.is_blacklisted = false;
-        agent_record.failed_attempts = 0;
+        emit!(ReportResolvedEvent {
+            report: report.key(),
+            agent: report.agent,
+            status: report.status,
+            bond_recipient: report.reporter,
+            bond,
+        });
+
+        Ok(())
+    }
+
+    /// Dismiss a pending report as unfounded (moderators only). Once
+    /// `required_confirmations` moderators agree the reporter's bond is
+    /// forfeited to the reported agent.
+    pub fn dismiss_report(ctx: Context<ModerateReport>) -> Result<()> {
+        let moderator = ctx.accounts.moderator.key();
+        let verification = &ctx.accounts.verification;
+        let report = &mut ctx.accounts.report;
 
         let clock = Clock::get()?;
-        emit!(AgentWhitelistedEvent {
-            agent: agent_record.agent,
-            timestamp: clock.unix_timestamp,
+        record_report_vote(verification, report, &moderator, false, clock.unix_timestamp)?;
+
+        emit!(ReportVoteCastEvent {
+            report: report.key(),
+            moderator,
+            confirm: false,
+        });
+
+        if report.dismissals < verification.required_confirmations {
+            return Ok(());
+        }
+
+        report.status = ReportStatus::Dismissed as u8;
+        report.resolved_at = clock.unix_timestamp;
+
+        let agent_record = &mut ctx.accounts.agent_record;
+        if agent_record.agent == Pubkey::default() {
+            agent_record.agent = report.agent;
+            agent_record.bump = ctx.bumps.agent_record;
+        }
+        release_pending_report(agent_record);
+
+        let bond = report.bond;
+        pay_out_bond(
+            &ctx.accounts.report.to_account_info(),
+            &ctx.accounts.reported_agent.to_account_info(),
+            bond,
+        )?;
+
+        let report = &ctx.accounts.report;
+        emit!(ReportResolvedEvent {
+            report: report.key(),
+            agent: report.agent,
+            status: report.status,
+            bond_recipient: report.agent,
+            bond,
         });
 
         Ok(())
     }
 
-    pub fn set_zkp_verifier(ctx: Context<UpdateConfig>, verifier: Option<Pubkey>) -> Result<()> {
-        let verification = &mut ctx.accounts.verification;
-        verification.zkp_verifier = verifier;
+    /// Close a report moderators left pending past `REPORT_TIMEOUT`. Nobody
+    /// judged it, so the bond goes back to the reporter.
+    pub fn expire_report(ctx: Context<ExpireReport>) -> Result<()> {
+        let report = &mut ctx.accounts.report;
+        require!(report.status == ReportStatus::Pending as u8, ErrorCode::ReportNotPending);
 
-        emit!(ZKPVerifierUpdatedEvent {
-            old_verifier: None,
-            new_verifier: verifier,
+        let clock = Clock::get()?;
+        let expires_at = report.created_at
+            .checked_add(REPORT_TIMEOUT)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(clock.unix_timestamp >= expires_at, ErrorCode::ReportNotExpired);
+
+        report.status = ReportStatus::Expired as u8;
+        report.resolved_at = clock.unix_timestamp;
+
+        release_pending_report(&mut ctx.accounts.agent_record);
+
+        let bond = report.bond;
+        pay_out_bond(
+            &ctx.accounts.report.to_account_info(),
+            &ctx.accounts.reporter.to_account_info(),
+            bond,
+        )?;
+
+        let report = &ctx.accounts.report;
+        emit!(ReportResolvedEvent {
+            report: report.key(),
+            agent: report.agent,
+            status: report.status,
+            bond_recipient: report.reporter,
+            bond,
         });
 
         Ok(())
     }
 
-    pub fn set_verification_mode(ctx: Context<UpdateConfig>, mode: u8) -> Result<()> {
-        require!(mode <= 2, ErrorCode::InvalidVerificationMode);
-        
+    pub fn add_reporter(ctx: Context<UpdateConfig>, reporter: Pubkey) -> Result<()> {
         let verification = &mut ctx.accounts.verification;
-        verification.verification_mode = mode;
+        require!((verification.num_reporters as usize) < MAX_REPORTERS, ErrorCode::MaxReportersReached);
+        require!(!is_reporter(verification, &reporter), ErrorCode::ReporterAlreadyExists);
 
-        emit!(VerificationModeUpdatedEvent {
-            new_mode: mode,
+        let idx = verification.num_reporters as usize;
+        verification.reporters[idx] = reporter;
+        verification.num_reporters = verification.num_reporters.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(ReporterAddedEvent {
+            reporter,
         });
 
         Ok(())
     }
 
-    pub fn set_verification_timeout(ctx: Context<UpdateConfig>, timeout: i64) -> Result<()> {
-        require!(timeout > 0, ErrorCode::TimeoutMustBeGreaterThanZero);
-        
+    pub fn remove_reporter(ctx: Context<UpdateConfig>, reporter: Pubkey) -> Result<()> {
         let verification = &mut ctx.accounts.verification;
-        let old_timeout = verification.verification_timeout;
-        verification.verification_timeout = timeout;
+        let count = verification.num_reporters as usize;
+        let idx = verification.reporters[..count]
+            .iter()
+            .position(|r| *r == reporter)
+            .ok_or(ErrorCode::NotReporter)?;
+
+        verification.reporters[idx] = verification.reporters[count - 1];
+        verification.reporters[count - 1] = Pubkey::default();
+        verification.num_reporters -= 1;
+
+        emit!(ReporterRemovedEvent {
+            reporter,
+        });
 
-        emit!(VerificationTimeoutUpdatedEvent {
-            old_timeout,
-            new_timeout: timeout,
+        Ok(())
+    }
+
+    pub fn add_moderator(ctx: Context<UpdateConfig>, moderator: Pubkey) -> Result<()> {
+        let verification = &mut ctx.accounts.verification;
+        require!((verification.num_moderators as usize) < MAX_MODERATORS, ErrorCode::MaxModeratorsReached);
+        require!(!is_moderator(verification, &moderator), ErrorCode::ModeratorAlreadyExists);
+
+        let idx = verification.num_moderators as usize;
+        verification.moderators[idx] = moderator;
+        verification.num_moderators = verification.num_moderators.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(ModeratorAddedEvent {
+            moderator,
         });
 
         Ok(())
     }
 
-    pub fn set_max_verification_attempts(ctx: Context<UpdateConfig>, attempts: u8) -> Result<()> {
-        require!(attempts > 0, ErrorCode::AttemptsMustBeGreaterThanZero);
-        
+    pub fn remove_moderator(ctx: Context<UpdateConfig>, moderator: Pubkey) -> Result<()> {
         let verification = &mut ctx.accounts.verification;
-        let old_attempts = verification.max_verification_attempts;
-        verification.max_verification_attempts = attempts;
+        let count = verification.num_moderators as usize;
+        let idx = verification.moderators[..count]
+            .iter()
+            .position(|m| *m == moderator)
+            .ok_or(ErrorCode::NotModerator)?;
+        require!(
+            count > verification.required_confirmations as usize,
+            ErrorCode::InvalidRequiredConfirmations
+        );
 
-        emit!(MaxVerificationAttemptsUpdatedEvent {
-            old_attempts,
-            new_attempts: attempts,
+        verification.moderators[idx] = verification.moderators[count - 1];
+        verification.moderators[count - 1] = Pubkey::default();
+        verification.num_moderators -= 1;
+
+        emit!(ModeratorRemovedEvent {
+            moderator,
         });
 
         Ok(())
     }
 
-    pub fn set_reputation_threshold(ctx: Context<UpdateConfig>, threshold: u64) -> Result<()> {
+    pub fn set_required_confirmations(ctx: Context<UpdateConfig>, required_confirmations: u8) -> Result<()> {
         let verification = &mut ctx.accounts.verification;
-        let old_threshold = verification.reputation_threshold;
-        verification.reputation_threshold = threshold;
+        require!(
+            required_confirmations > 0 && required_confirmations <= verification.num_moderators,
+            ErrorCode::InvalidRequiredConfirmations
+        );
 
-        emit!(ReputationThresholdUpdatedEvent {
-            old_threshold,
-            new_threshold: threshold,
+        let old_required_confirmations = verification.required_confirmations;
+        verification.required_confirmations = required_confirmations;
+
+        emit!(RequiredConfirmationsUpdatedEvent {
+            old_required_confirmations,
+            new_required_confirmations: required_confirmations,
         });
 
         Ok(())
     }
-}
 
-// ============ Accounts ============
+    pub fn set_report_bond(ctx: Context<UpdateConfig>, report_bond: u64) -> Result<()> {
+        let verification = &mut ctx.accounts.verification;
+        let old_report_bond = verification.report_bond;
+        verification.report_bond = report_bond;
 
-#[derive(Accounts)]
+        emit!(ReportBondUpdatedEvent {
+            old_report_bond,
+            new_report_bond: report_bond,
+        });
+
+        Ok(())
+    }
+
+    /// Set how long a blacklisting of the given severity lasts; 0 makes it permanent
+    pub fn set_blacklist_duration(ctx: Context<UpdateConfig>, severity: u8, duration: i64) -> Result<()> {
+        require!((severity as usize) < NUM_SEVERITIES, ErrorCode::InvalidSeverity);
+        require!(duration >= 0, ErrorCode::InvalidBlacklistDuration);
+
+        let verification = &mut ctx.accounts.verification;
+        let old_duration = verification.blacklist_durations[severity as usize];
+        verification.blacklist_durations[severity as usize] = duration;
+
+        emit!(BlacklistDurationUpdatedEvent {
+            severity,
+            old_duration,
+            new_duration: duration,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_appeal_stake(ctx: Context<UpdateConfig>, appeal_stake: u64) -> Result<()> {
+        let verification = &mut ctx.accounts.verification;
+        let old_appeal_stake = verification.appeal_stake;
+        verification.appeal_stake = appeal_stake;
+
+        emit!(AppealStakeUpdatedEvent {
+            old_appeal_stake,
+            new_appeal_stake: appeal_stake,
+        });
+
+        Ok(())
+    }
+
+    /// Set how long it takes for one failed verification attempt to be forgiven
+    pub fn set_attempt_cooldown(ctx: Context<UpdateConfig>, cooldown: i64) -> Result<()> {
+        require!(cooldown > 0, ErrorCode::TimeoutMustBeGreaterThanZero);
+
+        let verification = &mut ctx.accounts.verification;
+        let old_cooldown = verification.attempt_cooldown;
+        verification.attempt_cooldown = cooldown;
+
+        emit!(AttemptCooldownUpdatedEvent {
+            old_cooldown,
+            new_cooldown: cooldown,
+        });
+
+        Ok(())
+    }
+
+    /// Appeal an active blacklisting (the blacklisted agent only). The agent posts
+    /// `appeal_stake` lamports, returned if the appeal is granted and paid to the
+    /// authority treasury if it is denied. One appeal per blacklisting.
+    pub fn appeal_blacklist(ctx: Context<AppealBlacklist>, evidence_cid: String) -> Result<()> {
+        require!(
+            !evidence_cid.is_empty() && evidence_cid.len() <= MAX_EVIDENCE_CID_LEN,
+            ErrorCode::InvalidEvidenceCid
+        );
+
+        let clock = Clock::get()?;
+        let agent_record = &mut ctx.accounts.agent_record;
+        require!(agent_record.is_blacklisted_at(clock.unix_timestamp), ErrorCode::AgentNotBlacklisted);
+        require!(agent_record.appeal_status == AppealStatus::None as u8, ErrorCode::AppealNotAllowed);
+
+        let stake = ctx.accounts.verification.appeal_stake;
+        if stake > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.signer.to_account_info(),
+                        to: ctx.accounts.appeal.to_account_info(),
+                    },
+                ),
+                stake,
+            )?;
+        }
+
+        agent_record.appeal_status = AppealStatus::Pending as u8;
+
+        let appeal = &mut ctx.accounts.appeal;
+        appeal.agent = agent_record.agent;
+        appeal.evidence_cid = evidence_cid.clone();
+        appeal.stake = stake;
+        appeal.voters = [Pubkey::default(); MAX_MODERATORS];
+        appeal.num_votes = 0;
+        appeal.grants = 0;
+        appeal.denials = 0;
+        appeal.filed_at = clock.unix_timestamp;
+        appeal.bump = ctx.bumps.appeal;
+
+        emit!(BlacklistAppealFiledEvent {
+            agent: appeal.agent,
+            evidence_cid,
+            stake,
+        });
+
+        Ok(())
+    }
+
+    /// Vote to grant a pending appeal (moderators), or grant it outright
+    /// (authority). A granted appeal lifts the blacklisting and refunds the stake.
+    pub fn grant_appeal(ctx: Context<ResolveAppeal>) -> Result<()> {
+        let resolver = ctx.accounts.resolver.key();
+        let verification = &ctx.accounts.verification;
+        let appeal = &mut ctx.accounts.appeal;
+
+        let resolved = if resolver == verification.authority {
+            true
+        } else {
+            record_appeal_vote(verification, appeal, &resolver)?;
+            appeal.grants = appeal.grants.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+            emit!(AppealVoteCastEvent {
+                agent: appeal.agent,
+                moderator: resolver,
+                grant: true,
+            });
+
+            appeal.grants >= verification.required_confirmations
+        };
+        if !resolved {
+            return Ok(());
+        }
+
+        let agent_record = &mut ctx.accounts.agent_record;
+        agent_record.is_blacklisted = false;
+        agent_record.failed_attempts = 0;
+        agent_record.appeal_status = AppealStatus::Granted as u8;
+
+        let stake = appeal.stake;
+        ctx.accounts.appeal.close(ctx.accounts.agent.to_account_info())?;
+
+        let clock = Clock::get()?;
+        emit!(BlacklistAppealResolvedEvent {
+            agent: agent_record.agent,
+            granted: true,
+            stake,
+            timestamp: clock.unix_timestamp,
+        });
+
+        emit!(AgentWhitelistedEvent {
+            agent: agent_record.agent,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Vote to deny a pending appeal (moderators), or deny it outright
+    /// (authority). A denied appeal forfeits the stake and cannot be refiled.
+    pub fn deny_appeal(ctx: Context<ResolveAppeal>) -> Result<()> {
+        let resolver = ctx.accounts.resolver.key();
+        let verification = &ctx.accounts.verification;
+        let appeal = &mut ctx.accounts.appeal;
+
+        let resolved = if resolver == verification.authority {
+            true
+        } else {
+            record_appeal_vote(verification, appeal, &resolver)?;
+            appeal.denials = appeal.denials.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+            emit!(AppealVoteCastEvent {
+                agent: appeal.agent,
+                moderator: resolver,
+                grant: false,
+            });
+
+            appeal.denials >= verification.required_confirmations
+        };
+        if !resolved {
+            return Ok(());
+        }
+
+        let agent_record = &mut ctx.accounts.agent_record;
+        agent_record.appeal_status = AppealStatus::Denied as u8;
+
+        let stake = appeal.stake;
+        let appeal_info = ctx.accounts.appeal.to_account_info();
+        pay_out_bond(&appeal_info, &ctx.accounts.treasury.to_account_info(), stake)?;
+        ctx.accounts.appeal.close(ctx.accounts.agent.to_account_info())?;
+
+        emit!(BlacklistAppealResolvedEvent {
+            agent: agent_record.agent,
+            granted: false,
+            stake,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Lift a blacklisting (authority only). A pending appeal is closed and
+    /// its stake refunded to the agent.
+    pub fn remove_from_blacklist(ctx: Context<RemoveFromBlacklist>) -> Result<()> {
+        let agent_record = &mut ctx.accounts.agent_record;
+        require!(agent_record.is_blacklisted, ErrorCode::AgentNotBlacklisted);
+
+        if agent_record.appeal_status == AppealStatus::Pending as u8 {
+            let appeal = ctx.accounts.appeal.as_ref().ok_or(ErrorCode::AppealAccountRequired)?;
+            let agent = ctx.accounts.agent.as_ref().ok_or(ErrorCode::AppealAccountRequired)?;
+            appeal.close(agent.to_account_info())?;
+        }
+
+        agent_record.is_blacklisted = false;
+        agent_record.failed_attempts = 0;
+        agent_record.severity = 0;
+        agent_record.blacklisted_at = 0;
+        agent_record.blacklist_expires_at = 0;
+        agent_record.appeal_status = AppealStatus::None as u8;
+
+        let clock = Clock::get()?;
+        emit!(AgentWhitelistedEvent {
+            agent: agent_record.agent,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Set or rotate the Groth16 verifying key for identity proofs (authority only)
+    pub fn set_verifying_key(
+        ctx: Context<SetVerifyingKey>,
+        alpha_g1: [u8; 64],
+        beta_g2: [u8; 128],
+        gamma_g2: [u8; 128],
+        delta_g2: [u8; 128],
+        ic: Vec<[u8; 64]>,
+    ) -> Result<()> {
+        require!(
+            ic.len() >= 2 && ic.len() <= MAX_PUBLIC_INPUTS + 1,
+            ErrorCode::InvalidVerifyingKey
+        );
+
+        let verifying_key = &mut ctx.accounts.verifying_key;
+        verifying_key.verification = ctx.accounts.verification.key();
+        verifying_key.alpha_g1 = alpha_g1;
+        verifying_key.beta_g2 = beta_g2;
+        verifying_key.gamma_g2 = gamma_g2;
+        verifying_key.delta_g2 = delta_g2;
+        verifying_key.ic = ic;
+        verifying_key.bump = ctx.bumps.verifying_key;
+
+        emit!(VerifyingKeyUpdatedEvent {
+            authority: ctx.accounts.authority.key(),
+            public_inputs: (verifying_key.ic.len() - 1) as u8,
+        });
+
+        Ok(())
+    }
+
+    /// Convert a `Verification` account created before proofs were checked
+    /// in-program. The old layout carried a `zkp_verifier` key, which is dropped
+    /// in favour of the `VerifyingKey` account; the account is reallocated and
+    /// fields added since get their `initialize` defaults (authority only).
+    pub fn migrate_verification(ctx: Context<MigrateVerification>) -> Result<()> {
+        let info = ctx.accounts.verification.to_account_info();
+        require!(info.data_len() < 8 + Verification::LEN, ErrorCode::AlreadyMigrated);
+
+        let legacy = {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == Verification::DISCRIMINATOR,
+                ErrorCode::InvalidLegacyAccount
+            );
+            LegacyVerification::deserialize(&mut &data[8..])
+                .map_err(|_| error!(ErrorCode::InvalidLegacyAccount))?
+        };
+        require_keys_eq!(legacy.authority, ctx.accounts.authority.key(), ErrorCode::Unauthorized);
+
+        // Top up rent for the larger account before growing it
+        let required = Rent::get()?.minimum_balance(8 + Verification::LEN);
+        let shortfall = required.saturating_sub(info.lamports());
+        if shortfall > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.authority.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                shortfall,
+            )?;
+        }
+        info.realloc(8 + Verification::LEN, false)?;
+
+        let verification = Verification {
+            authority: legacy.authority,
+            agent_network: legacy.agent_network,
+            verification_timeout: legacy.verification_timeout,
+            max_verification_attempts: legacy.max_verification_attempts,
+            reputation_threshold: legacy.reputation_threshold,
+            total_verifications: legacy.total_verifications,
+            total_successful_verifications: legacy.total_successful_verifications,
+            total_failed_verifications: legacy.total_failed_verifications,
+            verification_mode: legacy.verification_mode,
+            reviewers: [Pubkey::default(); MAX_REVIEWERS],
+            num_reviewers: 0,
+            required_approvals: 1,
+            reporters: [Pubkey::default(); MAX_REPORTERS],
+            num_reporters: 0,
+            moderators: [Pubkey::default(); MAX_MODERATORS],
+            num_moderators: 0,
+            required_confirmations: 1,
+            report_bond: 0,
+            total_reports: 0,
+            blacklist_durations: DEFAULT_BLACKLIST_DURATIONS,
+            appeal_stake: 0,
+            attempt_cooldown: DEFAULT_ATTEMPT_COOLDOWN,
+            bump: legacy.bump,
+        };
+        let mut data = info.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data;
+        verification.try_serialize(&mut writer)?;
+
+        emit!(VerificationMigratedEvent {
+            verification: info.key(),
+            dropped_zkp_verifier: legacy.zkp_verifier,
+        });
+
+        Ok(())
+    }
+
+    /// Grow an `AgentRecord` created before the blacklist fields were added to
+    /// the current layout. Permissionless; the new fields start zeroed, which
+    /// keeps any existing blacklisting permanent with no appeal filed and no
+    /// reports counted as pending.
+    pub fn migrate_agent_record(ctx: Context<MigrateAgentRecord>, _agent: Pubkey) -> Result<()> {
+        let info = ctx.accounts.agent_record.to_account_info();
+        require!(info.data_len() < 8 + AgentRecord::LEN, ErrorCode::AlreadyMigrated);
+        {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 + AgentRecord::LEGACY_LEN && data[..8] == AgentRecord::DISCRIMINATOR,
+                ErrorCode::InvalidLegacyAccount
+            );
+        }
+
+        let required = Rent::get()?.minimum_balance(8 + AgentRecord::LEN);
+        let shortfall = required.saturating_sub(info.lamports());
+        if shortfall > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                shortfall,
+            )?;
+        }
+        info.realloc(8 + AgentRecord::LEN, true)?;
+
+        Ok(())
+    }
+
+    pub fn set_verification_mode(ctx: Context<UpdateConfig>, mode: u8) -> Result<()> {
+        require!(mode <= 2, ErrorCode::InvalidVerificationMode);
+        
+        let verification = &mut ctx.accounts.verification;
+        verification.verification_mode = mode;
+
+        emit!(VerificationModeUpdatedEvent {
+            new_mode: mode,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_verification_timeout(ctx: Context<UpdateConfig>, timeout: i64) -> Result<()> {
+        require!(timeout > 0, ErrorCode::TimeoutMustBeGreaterThanZero);
+        
+        let verification = &mut ctx.accounts.verification;
+        let old_timeout = verification.verification_timeout;
+        verification.verification_timeout = timeout;
+
+        emit!(VerificationTimeoutUpdatedEvent {
+            old_timeout,
+            new_timeout: timeout,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_max_verification_attempts(ctx: Context<UpdateConfig>, attempts: u8) -> Result<()> {
+        require!(attempts > 0, ErrorCode::AttemptsMustBeGreaterThanZero);
+        
+        let verification = &mut ctx.accounts.verification;
+        let old_attempts = verification.max_verification_attempts;
+        verification.max_verification_attempts = attempts;
+
+        emit!(MaxVerificationAttemptsUpdatedEvent {
+            old_attempts,
+            new_attempts: attempts,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_reputation_threshold(ctx: Context<UpdateConfig>, threshold: u64) -> Result<()> {
+        let verification = &mut ctx.accounts.verification;
+        let old_threshold = verification.reputation_threshold;
+        verification.reputation_threshold = threshold;
+
+        emit!(ReputationThresholdUpdatedEvent {
+            old_threshold,
+            new_threshold: threshold,
+        });
+
+        Ok(())
+    }
+}
+
+// ============ Accounts ============
+
+#[derive(Accounts)]
 pub struct Initialize<'info> {
     #[account(
         init,
@@ -351,7 +1472,7 @@ pub struct Initialize<'info> {
 }
 
 #[derive(Accounts)]
-#[instruction(did_document: String, public_key: String, commitment: [u8; 32], nullifier: [u8; 32], proof: [u8; 8])]
+#[instruction(public_key: String, commitment: [u8; 32], nullifier: [u8; 32])]
 pub struct InitiateIdentityVerification<'info> {
     #[account(
         mut,
@@ -377,11 +1498,20 @@ pub struct InitiateIdentityVerification<'info> {
     pub agent: Account<'info, AgentRecord>,
     
     #[account(
+        init,
+        payer = signer,
+        space = 8 + NullifierRecord::LEN,
         seeds = [b"nullifier", nullifier.as_ref()],
         bump
     )]
     pub nullifier_record: Account<'info, NullifierRecord>,
     
+    #[account(
+        seeds = [b"did", signer.key().as_ref()],
+        bump = did_document.bump
+    )]
+    pub did_document: Account<'info, DidDocument>,
+    
     /// CHECK: Agent network program
     pub agent_network: UncheckedAccount<'info>,
     
@@ -414,24 +1544,95 @@ pub struct VerifyIdentity<'info> {
     )]
     pub agent: Account<'info, AgentRecord>,
     
+    #[account(
+        seeds = [b"verifying-key", verification.key().as_ref()],
+        bump = verifying_key.bump
+    )]
+    pub verifying_key: Account<'info, VerifyingKey>,
+    
+    /// Only needed when the proof completes verification (`ZkpAutomated` mode)
     #[account(
         init,
-        payer = authority,
+        payer = payer,
         space = 8 + IdentityProof::LEN,
         seeds = [b"identity-proof", session.agent.as_ref()],
         bump
     )]
-    pub identity_proof: Account<'info, IdentityProof>,
+    pub identity_proof: Option<Account<'info, IdentityProof>>,
     
     /// CHECK: Agent network program
     pub agent_network: UncheckedAccount<'info>,
     
     #[account(mut)]
-    pub authority: Signer<'info>,
+    pub payer: Signer<'info>,
     
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct ReviewSession<'info> {
+    #[account(
+        mut,
+        seeds = [b"verification", agent_network.key().as_ref()],
+        bump = verification.bump
+    )]
+    pub verification: Account<'info, Verification>,
+    
+    #[account(
+        mut,
+        seeds = [b"session", session.agent.as_ref(), &session.timestamp.to_le_bytes()],
+        bump = session.bump
+    )]
+    pub session: Account<'info, VerificationSession>,
+    
+    #[account(
+        mut,
+        seeds = [b"agent", session.agent.as_ref()],
+        bump = agent.bump
+    )]
+    pub agent: Account<'info, AgentRecord>,
+    
+    /// Only needed for the approval that reaches `required_approvals`
+    #[account(
+        init,
+        payer = reviewer,
+        space = 8 + IdentityProof::LEN,
+        seeds = [b"identity-proof", session.agent.as_ref()],
+        bump
+    )]
+    pub identity_proof: Option<Account<'info, IdentityProof>>,
+    
+    /// CHECK: Agent network program
+    pub agent_network: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub reviewer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RejectSession<'info> {
+    #[account(
+        mut,
+        seeds = [b"verification", agent_network.key().as_ref()],
+        bump = verification.bump
+    )]
+    pub verification: Account<'info, Verification>,
+    
+    #[account(
+        mut,
+        seeds = [b"session", session.agent.as_ref(), &session.timestamp.to_le_bytes()],
+        bump = session.bump
+    )]
+    pub session: Account<'info, VerificationSession>,
+    
+    /// CHECK: Agent network program
+    pub agent_network: UncheckedAccount<'info>,
+    
+    pub reviewer: Signer<'info>,
+}
+
 #[derive(Accounts)]
 #[instruction(agent: Pubkey, reputation: u64, proof: [u8; 8])]
 pub struct VerifyReputation<'info> {
@@ -467,28 +1668,391 @@ pub struct VerifyReputation<'info> {
 }
 
 #[derive(Accounts)]
-#[instruction(agent: Pubkey, behavior_type: String)]
+#[instruction(issuer: Pubkey)]
+pub struct RegisterIssuer<'info> {
+    #[account(
+        seeds = [b"verification", agent_network.key().as_ref()],
+        bump = verification.bump,
+        has_one = authority
+    )]
+    pub verification: Account<'info, Verification>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + IssuerRecord::LEN,
+        seeds = [b"issuer", issuer.as_ref()],
+        bump
+    )]
+    pub issuer_record: Account<'info, IssuerRecord>,
+    
+    /// CHECK: Agent network program
+    pub agent_network: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetIssuerStatus<'info> {
+    #[account(
+        seeds = [b"verification", agent_network.key().as_ref()],
+        bump = verification.bump,
+        has_one = authority
+    )]
+    pub verification: Account<'info, Verification>,
+    
+    #[account(
+        mut,
+        seeds = [b"issuer", issuer_record.issuer.as_ref()],
+        bump = issuer_record.bump
+    )]
+    pub issuer_record: Account<'info, IssuerRecord>,
+    
+    /// CHECK: Agent network program
+    pub agent_network: UncheckedAccount<'info>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(schema_id: [u8; 32], subject: Pubkey)]
+pub struct IssueAttestation<'info> {
+    #[account(
+        seeds = [b"issuer", issuer_record.issuer.as_ref()],
+        bump = issuer_record.bump
+    )]
+    pub issuer_record: Account<'info, IssuerRecord>,
+    
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + Attestation::LEN,
+        seeds = [b"attestation", issuer_record.issuer.as_ref(), subject.as_ref(), schema_id.as_ref()],
+        bump
+    )]
+    pub attestation: Account<'info, Attestation>,
+    
+    /// CHECK: Instructions sysvar, checked by address
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeAttestation<'info> {
+    #[account(
+        mut,
+        seeds = [b"attestation", attestation.issuer.as_ref(), attestation.subject.as_ref(), attestation.schema_id.as_ref()],
+        bump = attestation.bump,
+        has_one = issuer
+    )]
+    pub attestation: Account<'info, Attestation>,
+    
+    pub issuer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateDidDocument<'info> {
+    #[account(
+        init,
+        payer = agent,
+        space = 8 + DidDocument::LEN,
+        seeds = [b"did", agent.key().as_ref()],
+        bump
+    )]
+    pub did_document: Account<'info, DidDocument>,
+    
+    #[account(mut)]
+    pub agent: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDidDocument<'info> {
+    #[account(
+        mut,
+        seeds = [b"did", did_document.agent.as_ref()],
+        bump = did_document.bump,
+        constraint = did_document.controllers.contains(&controller.key()) @ ErrorCode::NotDidController
+    )]
+    pub did_document: Account<'info, DidDocument>,
+    
+    pub controller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(agent: Pubkey)]
 pub struct DetectMaliciousBehavior<'info> {
     #[account(
         mut,
+        seeds = [b"verification", agent_network.key().as_ref()],
+        bump = verification.bump
+    )]
+    pub verification: Account<'info, Verification>,
+    
+    #[account(
+        init,
+        payer = reporter,
+        space = 8 + MaliciousReport::LEN,
+        seeds = [b"report", agent.as_ref(), verification.total_reports.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub report: Account<'info, MaliciousReport>,
+    
+    #[account(
+        init_if_needed,
+        payer = reporter,
+        space = 8 + AgentRecord::LEN,
         seeds = [b"agent", agent.as_ref()],
+        bump
+    )]
+    pub agent_record: Account<'info, AgentRecord>,
+    
+    /// CHECK: Agent network program
+    pub agent_network: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub reporter: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ModerateReport<'info> {
+    #[account(
+        seeds = [b"verification", agent_network.key().as_ref()],
+        bump = verification.bump
+    )]
+    pub verification: Account<'info, Verification>,
+    
+    #[account(
+        mut,
+        seeds = [b"report", report.agent.as_ref(), report.report_id.to_le_bytes().as_ref()],
+        bump = report.bump
+    )]
+    pub report: Account<'info, MaliciousReport>,
+    
+    #[account(
+        init_if_needed,
+        payer = moderator,
+        space = 8 + AgentRecord::LEN,
+        seeds = [b"agent", report.agent.as_ref()],
+        bump
+    )]
+    pub agent_record: Account<'info, AgentRecord>,
+    
+    /// CHECK: Receives the bond back when the report is confirmed
+    #[account(mut, address = report.reporter)]
+    pub reporter: UncheckedAccount<'info>,
+    
+    /// CHECK: Receives the forfeited bond when the report is dismissed
+    #[account(mut, address = report.agent)]
+    pub reported_agent: UncheckedAccount<'info>,
+    
+    /// CHECK: Agent network program
+    pub agent_network: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub moderator: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExpireReport<'info> {
+    #[account(
+        mut,
+        seeds = [b"report", report.agent.as_ref(), report.report_id.to_le_bytes().as_ref()],
+        bump = report.bump
+    )]
+    pub report: Account<'info, MaliciousReport>,
+    
+    #[account(
+        mut,
+        seeds = [b"agent", report.agent.as_ref()],
         bump = agent_record.bump
     )]
     pub agent_record: Account<'info, AgentRecord>,
     
-    pub authority: Signer<'info>,
+    /// CHECK: Receives the bond back
+    #[account(mut, address = report.reporter)]
+    pub reporter: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AppealBlacklist<'info> {
+    #[account(
+        seeds = [b"verification", agent_network.key().as_ref()],
+        bump = verification.bump
+    )]
+    pub verification: Account<'info, Verification>,
+    
+    #[account(
+        mut,
+        seeds = [b"agent", signer.key().as_ref()],
+        bump = agent_record.bump
+    )]
+    pub agent_record: Account<'info, AgentRecord>,
+    
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + BlacklistAppeal::LEN,
+        seeds = [b"appeal", signer.key().as_ref()],
+        bump
+    )]
+    pub appeal: Account<'info, BlacklistAppeal>,
+    
+    /// CHECK: Agent network program
+    pub agent_network: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveAppeal<'info> {
+    #[account(
+        seeds = [b"verification", agent_network.key().as_ref()],
+        bump = verification.bump
+    )]
+    pub verification: Account<'info, Verification>,
+    
+    #[account(
+        mut,
+        seeds = [b"agent", appeal.agent.as_ref()],
+        bump = agent_record.bump
+    )]
+    pub agent_record: Account<'info, AgentRecord>,
+    
+    #[account(
+        mut,
+        seeds = [b"appeal", appeal.agent.as_ref()],
+        bump = appeal.bump
+    )]
+    pub appeal: Account<'info, BlacklistAppeal>,
+    
+    /// CHECK: Appellant; receives the appeal account rent and any refunded stake
+    #[account(mut, address = appeal.agent)]
+    pub agent: UncheckedAccount<'info>,
+    
+    /// CHECK: Receives the stake of a denied appeal
+    #[account(mut, address = verification.authority)]
+    pub treasury: UncheckedAccount<'info>,
+    
+    /// CHECK: Agent network program
+    pub agent_network: UncheckedAccount<'info>,
+    
+    pub resolver: Signer<'info>,
 }
 
 #[derive(Accounts)]
 pub struct RemoveFromBlacklist<'info> {
+    #[account(
+        seeds = [b"verification", agent_network.key().as_ref()],
+        bump = verification.bump,
+        has_one = authority
+    )]
+    pub verification: Account<'info, Verification>,
+    
     #[account(
         mut,
         seeds = [b"agent", agent_record.agent.as_ref()],
         bump = agent_record.bump
     )]
-    pub agent_record: Account<'info, AgentRecord>,
+    pub agent_record: Account<'info, AgentRecord>,
+    
+    /// Only needed when an appeal is pending
+    #[account(
+        mut,
+        seeds = [b"appeal", agent_record.agent.as_ref()],
+        bump = appeal.bump
+    )]
+    pub appeal: Option<Account<'info, BlacklistAppeal>>,
+    
+    /// CHECK: Appellant; receives the pending appeal's stake and rent
+    #[account(mut, address = agent_record.agent)]
+    pub agent: Option<UncheckedAccount<'info>>,
+    
+    /// CHECK: Agent network program
+    pub agent_network: UncheckedAccount<'info>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateVerification<'info> {
+    /// CHECK: Pre-upgrade `Verification` account, decoded by hand since it does not match the current layout
+    #[account(
+        mut,
+        seeds = [b"verification", agent_network.key().as_ref()],
+        bump,
+        owner = crate::ID
+    )]
+    pub verification: UncheckedAccount<'info>,
+    
+    /// CHECK: Agent network program
+    pub agent_network: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(agent: Pubkey)]
+pub struct MigrateAgentRecord<'info> {
+    /// CHECK: Pre-upgrade `AgentRecord` account, too short to deserialize as the current layout
+    #[account(
+        mut,
+        seeds = [b"agent", agent.as_ref()],
+        bump,
+        owner = crate::ID
+    )]
+    pub agent_record: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetVerifyingKey<'info> {
+    #[account(
+        seeds = [b"verification", agent_network.key().as_ref()],
+        bump = verification.bump,
+        has_one = authority
+    )]
+    pub verification: Account<'info, Verification>,
+    
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + VerifyingKey::LEN,
+        seeds = [b"verifying-key", verification.key().as_ref()],
+        bump
+    )]
+    pub verifying_key: Account<'info, VerifyingKey>,
+    
+    /// CHECK: Agent network program
+    pub agent_network: UncheckedAccount<'info>,
     
+    #[account(mut)]
     pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
@@ -509,6 +2073,22 @@ pub struct UpdateConfig<'info> {
 
 // ============ State ============
 
+const MAX_REVIEWERS: usize = 10;
+const MAX_REPORTERS: usize = 10;
+const MAX_MODERATORS: usize = 10;
+const MAX_EVIDENCE_CID_LEN: usize = 100;
+const NUM_SEVERITIES: usize = 4;
+/// Blacklist durations by severity (Low, Medium, High, Critical); 0 = permanent
+const DEFAULT_BLACKLIST_DURATIONS: [i64; NUM_SEVERITIES] = [
+    7 * 24 * 60 * 60,
+    30 * 24 * 60 * 60,
+    180 * 24 * 60 * 60,
+    0,
+];
+const DEFAULT_ATTEMPT_COOLDOWN: i64 = 24 * 60 * 60;
+/// How long a report may stay pending before anyone can expire it
+pub const REPORT_TIMEOUT: i64 = 30 * 24 * 60 * 60;
+
 #[account]
 pub struct Verification {
     pub authority: Pubkey,
@@ -519,13 +2099,44 @@ pub struct Verification {
     pub total_verifications: u64,
     pub total_successful_verifications: u64,
     pub total_failed_verifications: u64,
-    pub zkp_verifier: Option<Pubkey>,
     pub verification_mode: u8,
+    pub reviewers: [Pubkey; MAX_REVIEWERS],
+    pub num_reviewers: u8,
+    pub required_approvals: u8,
+    pub reporters: [Pubkey; MAX_REPORTERS],
+    pub num_reporters: u8,
+    pub moderators: [Pubkey; MAX_MODERATORS],
+    pub num_moderators: u8,
+    pub required_confirmations: u8,
+    pub report_bond: u64, // Lamports posted by the reporter per report
+    pub total_reports: u64,
+    pub blacklist_durations: [i64; NUM_SEVERITIES],
+    pub appeal_stake: u64, // Lamports posted by an agent per appeal
+    pub attempt_cooldown: i64, // Seconds for one failed attempt to decay
     pub bump: u8,
 }
 
 impl Verification {
-    pub const LEN: usize = 32 + 32 + 8 + 1 + 8 + 8 + 8 + 8 + (1 + 32) + 1 + 1;
+    pub const LEN: usize = 32 + 32 + 8 + 1 + 8 + 8 + 8 + 8 + 1 + (32 * MAX_REVIEWERS) + 1 + 1
+        + (32 * MAX_REPORTERS) + 1 + (32 * MAX_MODERATORS) + 1 + 1 + 8 + 8
+        + (8 * NUM_SEVERITIES) + 8 + 8 + 1;
+}
+
+/// `Verification` layout before proofs were checked in-program, read only by
+/// `migrate_verification`
+#[derive(AnchorDeserialize)]
+pub struct LegacyVerification {
+    pub authority: Pubkey,
+    pub agent_network: Pubkey,
+    pub verification_timeout: i64,
+    pub max_verification_attempts: u8,
+    pub reputation_threshold: u64,
+    pub total_verifications: u64,
+    pub total_successful_verifications: u64,
+    pub total_failed_verifications: u64,
+    pub zkp_verifier: Option<Pubkey>,
+    pub verification_mode: u8,
+    pub bump: u8,
 }
 
 #[account]
@@ -538,13 +2149,16 @@ pub struct VerificationSession {
     pub nullifier: [u8; 32],
     pub timestamp: i64,
     pub status: u8,
-    pub proof: [u8; 8],
+    pub proof: Groth16Proof,
     pub is_valid: bool,
+    pub approvers: [Pubkey; MAX_REVIEWERS],
+    pub num_approvals: u8,
+    pub rejection_reason: u8,
     pub bump: u8,
 }
 
 impl VerificationSession {
-    pub const LEN: usize = 32 + 32 + 500 + 200 + 32 + 32 + 8 + 1 + 8 + 1 + 1;
+    pub const LEN: usize = 32 + 32 + 500 + 200 + 32 + 32 + 8 + 1 + Groth16Proof::LEN + 1 + (32 * MAX_REVIEWERS) + 1 + 1 + 1;
 }
 
 #[account]
@@ -554,10 +2168,70 @@ pub struct AgentRecord {
     pub last_failed_attempt: i64,
     pub is_blacklisted: bool,
     pub bump: u8,
+    pub severity: u8,
+    pub blacklisted_at: i64,
+    pub blacklist_expires_at: i64, // 0 = permanent
+    pub appeal_status: u8,
+    pub pending_reports: u32, // Reports filed and not yet confirmed or dismissed
+    pub last_report_at: i64,
 }
 
 impl AgentRecord {
-    pub const LEN: usize = 32 + 1 + 8 + 1 + 1;
+    pub const LEN: usize = 32 + 1 + 8 + 1 + 1 + 1 + 8 + 8 + 1 + 4 + 8;
+    /// Size before the blacklist fields, accepted by `migrate_agent_record`
+    pub const LEGACY_LEN: usize = 32 + 1 + 8 + 1 + 1;
+
+    /// Whether the agent is blacklisted at `now`, taking expiry into account
+    pub fn is_blacklisted_at(&self, now: i64) -> bool {
+        self.is_blacklisted && (self.blacklist_expires_at == 0 || now < self.blacklist_expires_at)
+    }
+
+    /// Whether reports still await moderation at `now`. Once the newest one
+    /// is past `REPORT_TIMEOUT` they can all be expired and no longer count.
+    pub fn has_pending_reports_at(&self, now: i64) -> bool {
+        self.pending_reports > 0 && now < self.last_report_at.saturating_add(REPORT_TIMEOUT)
+    }
+}
+
+#[account]
+pub struct MaliciousReport {
+    pub report_id: u64,
+    pub agent: Pubkey,
+    pub reporter: Pubkey,
+    pub behavior_type: u8,
+    pub severity: u8,
+    pub evidence_cid: String,
+    pub bond: u64,
+    pub status: u8,
+    pub voters: [Pubkey; MAX_MODERATORS],
+    pub confirms: [bool; MAX_MODERATORS], // Whether the matching voter confirmed
+    pub num_votes: u8,
+    pub confirmations: u8,
+    pub dismissals: u8,
+    pub created_at: i64,
+    pub resolved_at: i64,
+    pub bump: u8,
+}
+
+impl MaliciousReport {
+    pub const LEN: usize = 8 + 32 + 32 + 1 + 1 + (4 + MAX_EVIDENCE_CID_LEN) + 8 + 1 + (32 * MAX_MODERATORS) + MAX_MODERATORS + 1 + 1 + 1 + 8 + 8 + 1;
+}
+
+#[account]
+pub struct BlacklistAppeal {
+    pub agent: Pubkey,
+    pub evidence_cid: String,
+    pub stake: u64,
+    pub voters: [Pubkey; MAX_MODERATORS],
+    pub num_votes: u8,
+    pub grants: u8,
+    pub denials: u8,
+    pub filed_at: i64,
+    pub bump: u8,
+}
+
+impl BlacklistAppeal {
+    pub const LEN: usize = 32 + (4 + MAX_EVIDENCE_CID_LEN) + 8 + (32 * MAX_MODERATORS) + 1 + 1 + 1 + 8 + 1;
 }
 
 #[account]
@@ -567,14 +2241,14 @@ pub struct IdentityProof {
     pub public_key: String,
     pub commitment: [u8; 32],
     pub nullifier: [u8; 32],
-    pub proof: [u8; 8],
+    pub proof: Groth16Proof,
     pub timestamp: i64,
     pub is_verified: bool,
     pub bump: u8,
 }
 
 impl IdentityProof {
-    pub const LEN: usize = 32 + 500 + 200 + 32 + 32 + 8 + 8 + 1 + 1;
+    pub const LEN: usize = 32 + 500 + 200 + 32 + 32 + Groth16Proof::LEN + 8 + 1 + 1;
 }
 
 #[account]
@@ -591,6 +2265,101 @@ impl ReputationProof {
     pub const LEN: usize = 32 + 8 + 8 + 8 + 1 + 1;
 }
 
+#[account]
+pub struct IssuerRecord {
+    pub issuer: Pubkey,
+    pub name: String,
+    pub is_active: bool,
+    pub registered_at: i64,
+    pub bump: u8,
+}
+
+impl IssuerRecord {
+    pub const LEN: usize = 32 + (4 + 64) + 1 + 8 + 1;
+}
+
+/// Credential issued by a registered issuer to a subject. Claims stay off-chain;
+/// only their hash is recorded together with the issuer's signature.
+#[account]
+pub struct Attestation {
+    pub issuer: Pubkey,
+    pub subject: Pubkey,
+    pub schema_id: [u8; 32],
+    pub claims_hash: [u8; 32],
+    pub issued_at: i64,
+    pub expires_at: i64,
+    pub signature: [u8; 64],
+    pub is_revoked: bool,
+    pub revoked_at: i64,
+    pub version: u64,
+    pub bump: u8,
+}
+
+impl Attestation {
+    pub const LEN: usize = 32 + 32 + 32 + 32 + 8 + 8 + 64 + 1 + 8 + 8 + 1;
+}
+
+const MAX_DID_CONTROLLERS: usize = 4;
+const MAX_VERIFICATION_METHODS: usize = 8;
+const MAX_SERVICES: usize = 4;
+const MAX_DID_FRAGMENT_LEN: usize = 32;
+const MAX_SERVICE_TYPE_LEN: usize = 32;
+const MAX_SERVICE_ENDPOINT_LEN: usize = 128;
+
+pub const RELATIONSHIP_AUTHENTICATION: u8 = 1;
+pub const RELATIONSHIP_ASSERTION_METHOD: u8 = 1 << 1;
+pub const RELATIONSHIP_KEY_AGREEMENT: u8 = 1 << 2;
+pub const RELATIONSHIP_CAPABILITY_INVOCATION: u8 = 1 << 3;
+pub const RELATIONSHIP_CAPABILITY_DELEGATION: u8 = 1 << 4;
+const RELATIONSHIP_ALL: u8 = (1 << 5) - 1;
+
+/// On-chain document behind `did:diap:<agent>`
+#[account]
+pub struct DidDocument {
+    pub agent: Pubkey,
+    pub controllers: Vec<Pubkey>,
+    pub verification_methods: Vec<VerificationMethod>,
+    pub services: Vec<ServiceEndpoint>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub bump: u8,
+}
+
+impl DidDocument {
+    pub const LEN: usize = 32
+        + (4 + MAX_DID_CONTROLLERS * 32)
+        + (4 + MAX_VERIFICATION_METHODS * VerificationMethod::LEN)
+        + (4 + MAX_SERVICES * ServiceEndpoint::LEN)
+        + 8
+        + 8
+        + 1;
+}
+
+/// Verification method; `id` is the fragment after `#`, `relationships` is a
+/// bitmask of the `RELATIONSHIP_*` flags
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct VerificationMethod {
+    pub id: String,
+    pub key_type: u8,
+    pub public_key: [u8; 32],
+    pub relationships: u8,
+}
+
+impl VerificationMethod {
+    pub const LEN: usize = (4 + MAX_DID_FRAGMENT_LEN) + 1 + 32 + 1;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ServiceEndpoint {
+    pub id: String,
+    pub service_type: String,
+    pub endpoint: String,
+}
+
+impl ServiceEndpoint {
+    pub const LEN: usize = (4 + MAX_DID_FRAGMENT_LEN) + (4 + MAX_SERVICE_TYPE_LEN) + (4 + MAX_SERVICE_ENDPOINT_LEN);
+}
+
 #[account]
 pub struct NullifierRecord {
     pub nullifier: [u8; 32],
@@ -602,6 +2371,36 @@ impl NullifierRecord {
     pub const LEN: usize = 32 + 1 + 1;
 }
 
+const MAX_PUBLIC_INPUTS: usize = 8;
+
+/// Groth16 verifying key over BN254, points in EIP-197 big-endian encoding
+#[account]
+pub struct VerifyingKey {
+    pub verification: Pubkey,
+    pub alpha_g1: [u8; 64],
+    pub beta_g2: [u8; 128],
+    pub gamma_g2: [u8; 128],
+    pub delta_g2: [u8; 128],
+    pub ic: Vec<[u8; 64]>,
+    pub bump: u8,
+}
+
+impl VerifyingKey {
+    pub const LEN: usize = 32 + 64 + 128 + 128 + 128 + (4 + (MAX_PUBLIC_INPUTS + 1) * 64) + 1;
+}
+
+/// Groth16 proof, points in EIP-197 big-endian encoding
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Groth16Proof {
+    pub a: [u8; 64],
+    pub b: [u8; 128],
+    pub c: [u8; 64],
+}
+
+impl Groth16Proof {
+    pub const LEN: usize = 64 + 128 + 64;
+}
+
 // ============ Events ============
 
 #[event]
@@ -632,10 +2431,106 @@ pub struct ReputationVerifiedEvent {
     pub timestamp: i64,
 }
 
+#[event]
+pub struct IssuerRegisteredEvent {
+    pub issuer: Pubkey,
+    pub name: String,
+}
+
+#[event]
+pub struct IssuerStatusUpdatedEvent {
+    pub issuer: Pubkey,
+    pub is_active: bool,
+}
+
+#[event]
+pub struct AttestationIssuedEvent {
+    pub issuer: Pubkey,
+    pub subject: Pubkey,
+    pub schema_id: [u8; 32],
+    pub claims_hash: [u8; 32],
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct AttestationRevokedEvent {
+    pub issuer: Pubkey,
+    pub subject: Pubkey,
+    pub schema_id: [u8; 32],
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DidDocumentCreatedEvent {
+    pub agent: Pubkey,
+}
+
+#[event]
+pub struct DidDocumentUpdatedEvent {
+    pub agent: Pubkey,
+    pub change: u8,
+    pub id: String,
+}
+
 #[event]
 pub struct AgentBlacklistedEvent {
     pub agent: Pubkey,
     pub reason: String,
+    pub severity: u8,
+    pub expires_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReportSubmittedEvent {
+    #[index]
+    pub report: Pubkey,
+    pub agent: Pubkey,
+    pub reporter: Pubkey,
+    pub behavior_type: u8,
+    pub severity: u8,
+    pub evidence_cid: String,
+    pub bond: u64,
+}
+
+#[event]
+pub struct ReportVoteCastEvent {
+    pub report: Pubkey,
+    pub moderator: Pubkey,
+    pub confirm: bool,
+}
+
+#[event]
+pub struct ReportResolvedEvent {
+    #[index]
+    pub report: Pubkey,
+    pub agent: Pubkey,
+    pub status: u8,
+    pub bond_recipient: Pubkey,
+    pub bond: u64,
+}
+
+#[event]
+pub struct BlacklistAppealFiledEvent {
+    #[index]
+    pub agent: Pubkey,
+    pub evidence_cid: String,
+    pub stake: u64,
+}
+
+#[event]
+pub struct AppealVoteCastEvent {
+    pub agent: Pubkey,
+    pub moderator: Pubkey,
+    pub grant: bool,
+}
+
+#[event]
+pub struct BlacklistAppealResolvedEvent {
+    #[index]
+    pub agent: Pubkey,
+    pub granted: bool,
+    pub stake: u64,
     pub timestamp: i64,
 }
 
@@ -646,9 +2541,104 @@ pub struct AgentWhitelistedEvent {
 }
 
 #[event]
-pub struct ZKPVerifierUpdatedEvent {
-    pub old_verifier: Option<Pubkey>,
-    pub new_verifier: Option<Pubkey>,
+pub struct SessionAwaitingReviewEvent {
+    pub session_id: [u8; 32],
+    pub agent: Pubkey,
+    pub required_approvals: u8,
+}
+
+#[event]
+pub struct SessionApprovedEvent {
+    pub session_id: [u8; 32],
+    pub reviewer: Pubkey,
+    pub approvals: u8,
+}
+
+#[event]
+pub struct SessionRejectedEvent {
+    pub session_id: [u8; 32],
+    pub agent: Pubkey,
+    pub reviewer: Pubkey,
+    pub reason: u8,
+}
+
+#[event]
+pub struct ReviewerAddedEvent {
+    pub reviewer: Pubkey,
+}
+
+#[event]
+pub struct ReviewerRemovedEvent {
+    pub reviewer: Pubkey,
+}
+
+#[event]
+pub struct ReporterAddedEvent {
+    pub reporter: Pubkey,
+}
+
+#[event]
+pub struct ReporterRemovedEvent {
+    pub reporter: Pubkey,
+}
+
+#[event]
+pub struct ModeratorAddedEvent {
+    pub moderator: Pubkey,
+}
+
+#[event]
+pub struct ModeratorRemovedEvent {
+    pub moderator: Pubkey,
+}
+
+#[event]
+pub struct RequiredConfirmationsUpdatedEvent {
+    pub old_required_confirmations: u8,
+    pub new_required_confirmations: u8,
+}
+
+#[event]
+pub struct ReportBondUpdatedEvent {
+    pub old_report_bond: u64,
+    pub new_report_bond: u64,
+}
+
+#[event]
+pub struct BlacklistDurationUpdatedEvent {
+    pub severity: u8,
+    pub old_duration: i64,
+    pub new_duration: i64,
+}
+
+#[event]
+pub struct AppealStakeUpdatedEvent {
+    pub old_appeal_stake: u64,
+    pub new_appeal_stake: u64,
+}
+
+#[event]
+pub struct AttemptCooldownUpdatedEvent {
+    pub old_cooldown: i64,
+    pub new_cooldown: i64,
+}
+
+#[event]
+pub struct RequiredApprovalsUpdatedEvent {
+    pub old_required_approvals: u8,
+    pub new_required_approvals: u8,
+}
+
+#[event]
+pub struct VerifyingKeyUpdatedEvent {
+    pub authority: Pubkey,
+    pub public_inputs: u8,
+}
+
+#[event]
+pub struct VerificationMigratedEvent {
+    pub verification: Pubkey,
+    pub dropped_zkp_verifier: Option<Pubkey>,
 }
 
 #[event]
@@ -716,6 +2706,108 @@ pub enum ErrorCode {
     InvalidBehaviorType,
     #[msg("Math overflow")]
     MathOverflow,
+    #[msg("Invalid verifying key")]
+    InvalidVerifyingKey,
+    #[msg("Session requires manual review")]
+    ManualReviewRequired,
+    #[msg("Review not required in current verification mode")]
+    ReviewNotRequired,
+    #[msg("Not a reviewer")]
+    NotReviewer,
+    #[msg("Reviewer already exists")]
+    ReviewerAlreadyExists,
+    #[msg("Max reviewers reached")]
+    MaxReviewersReached,
+    #[msg("Session already approved by reviewer")]
+    AlreadyApproved,
+    #[msg("Invalid required approvals")]
+    InvalidRequiredApprovals,
+    #[msg("Invalid rejection reason")]
+    InvalidRejectionReason,
+    #[msg("Identity proof account required")]
+    IdentityProofAccountRequired,
+    #[msg("Invalid issuer name")]
+    InvalidIssuerName,
+    #[msg("Issuer not active")]
+    IssuerNotActive,
+    #[msg("Invalid expiry")]
+    InvalidExpiry,
+    #[msg("Attestation revoked")]
+    AttestationRevoked,
+    #[msg("Missing Ed25519 signature instruction")]
+    MissingEd25519Instruction,
+    #[msg("Invalid Ed25519 signature instruction")]
+    InvalidEd25519Instruction,
+    #[msg("Missing issuer signature")]
+    MissingIssuerSignature,
+    #[msg("Not a DID controller")]
+    NotDidController,
+    #[msg("Invalid DID fragment")]
+    InvalidDidFragment,
+    #[msg("DID fragment already used")]
+    DuplicateDidFragment,
+    #[msg("DID fragment not found")]
+    DidFragmentNotFound,
+    #[msg("Invalid key type")]
+    InvalidKeyType,
+    #[msg("Invalid verification relationships")]
+    InvalidRelationships,
+    #[msg("New key must differ from current key")]
+    InvalidKeyRotation,
+    #[msg("Too many verification methods")]
+    TooManyVerificationMethods,
+    #[msg("Invalid service type")]
+    InvalidServiceType,
+    #[msg("Invalid service endpoint")]
+    InvalidServiceEndpoint,
+    #[msg("Too many services")]
+    TooManyServices,
+    #[msg("Too many controllers")]
+    TooManyControllers,
+    #[msg("Controller already exists")]
+    DuplicateController,
+    #[msg("Cannot remove last controller")]
+    LastController,
+    #[msg("Not a reporter")]
+    NotReporter,
+    #[msg("Reporter already exists")]
+    ReporterAlreadyExists,
+    #[msg("Max reporters reached")]
+    MaxReportersReached,
+    #[msg("Not a moderator")]
+    NotModerator,
+    #[msg("Moderator already exists")]
+    ModeratorAlreadyExists,
+    #[msg("Max moderators reached")]
+    MaxModeratorsReached,
+    #[msg("Invalid required confirmations")]
+    InvalidRequiredConfirmations,
+    #[msg("Invalid evidence CID")]
+    InvalidEvidenceCid,
+    #[msg("Report not pending")]
+    ReportNotPending,
+    #[msg("Moderator already voted on report")]
+    AlreadyVoted,
+    #[msg("Reporter cannot moderate own report")]
+    ReporterCannotModerate,
+    #[msg("Invalid blacklist severity")]
+    InvalidSeverity,
+    #[msg("Invalid blacklist duration")]
+    InvalidBlacklistDuration,
+    #[msg("Blacklisting cannot be appealed")]
+    AppealNotAllowed,
+    #[msg("Account already migrated")]
+    AlreadyMigrated,
+    #[msg("Invalid legacy account data")]
+    InvalidLegacyAccount,
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Report expired")]
+    ReportExpired,
+    #[msg("Report not expired yet")]
+    ReportNotExpired,
+    #[msg("Pending appeal account required")]
+    AppealAccountRequired,
 }
 
 // ============ Enums ============
@@ -726,6 +2818,8 @@ pub enum VerificationStatus {
     Verified = 1,
     Failed = 2,
     Expired = 3,
+    AwaitingReview = 4,
+    Rejected = 5,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
@@ -735,6 +2829,65 @@ pub enum VerificationMode {
     Hybrid = 2,
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RejectionReason {
+    InvalidDocument = 0,
+    KeyMismatch = 1,
+    InsufficientEvidence = 2,
+    SuspectedFraud = 3,
+    Other = 4,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BehaviorType {
+    Spam = 0,
+    Fraud = 1,
+    Attack = 2,
+    Abuse = 3,
+    Misbehavior = 4,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ReportStatus {
+    Pending = 0,
+    Confirmed = 1,
+    Dismissed = 2,
+    Expired = 3,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BlacklistSeverity {
+    Low = 0,
+    Medium = 1,
+    High = 2,
+    Critical = 3,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AppealStatus {
+    None = 0,
+    Pending = 1,
+    Granted = 2,
+    Denied = 3,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    Ed25519 = 0,
+    X25519 = 1,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DidChange {
+    MethodAdded = 0,
+    MethodRemoved = 1,
+    MethodRotated = 2,
+    ServiceAdded = 3,
+    ServiceRemoved = 4,
+    ControllerAdded = 5,
+    ControllerRemoved = 6,
+}
+
 // ============ Utilities ============
 
 fn generate_session_id(
@@ -755,12 +2908,296 @@ fn generate_session_id(
     hasher.result().to_bytes()
 }
 
-fn verify_zkp_proof(proof: [u8; 8], did_document: &str, public_key: &str) -> bool {
-    // Enhanced validation - checks proof structure
-    proof != [0u8; 8] && !did_document.is_empty() && did_document.len() <= 1000
-        && !public_key.is_empty() && public_key.len() <= 1000
-        // Basic proof structure validation (first byte should be a valid proof type)
-        && proof[0] > 0 && proof[0] <= 5
+/// Message an issuer signs for `issue_attestation`. `version` is the current
+/// `Attestation.version` (0 for a first issue).
+pub fn attestation_message(
+    issuer: &Pubkey,
+    schema_id: &[u8; 32],
+    subject: &Pubkey,
+    claims_hash: &[u8; 32],
+    expires_at: i64,
+    version: u64,
+) -> [u8; 32] {
+    hashv(&[
+        b"diap-attestation",
+        ID.as_ref(),
+        issuer.as_ref(),
+        schema_id,
+        subject.as_ref(),
+        claims_hash,
+        &expires_at.to_le_bytes(),
+        &version.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+/// Whether `attestation` proves that its subject holds credential `schema_id`
+/// from the issuer in `issuer_record`. Other programs can use this to gate
+/// actions after checking both accounts are owned by this program.
+pub fn holds_credential(
+    attestation: &Attestation,
+    issuer_record: &IssuerRecord,
+    schema_id: &[u8; 32],
+    now: i64,
+) -> bool {
+    issuer_record.is_active
+        && attestation.issuer == issuer_record.issuer
+        && attestation.schema_id == *schema_id
+        && !attestation.is_revoked
+        && (attestation.expires_at == 0 || now < attestation.expires_at)
+}
+
+/// DID URL fragments: 1-32 ASCII letters, digits, `-` or `_`
+fn is_valid_fragment(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_DID_FRAGMENT_LEN
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn is_reviewer(verification: &Verification, reviewer: &Pubkey) -> bool {
+    verification.reviewers[..verification.num_reviewers as usize].contains(reviewer)
+}
+
+fn is_reporter(verification: &Verification, reporter: &Pubkey) -> bool {
+    verification.reporters[..verification.num_reporters as usize].contains(reporter)
+}
+
+fn is_moderator(verification: &Verification, moderator: &Pubkey) -> bool {
+    verification.moderators[..verification.num_moderators as usize].contains(moderator)
+}
+
+fn parse_behavior_type(behavior_type: &str) -> Result<u8> {
+    let behavior_type = match behavior_type {
+        "SPAM" => BehaviorType::Spam,
+        "FRAUD" => BehaviorType::Fraud,
+        "ATTACK" => BehaviorType::Attack,
+        "ABUSE" => BehaviorType::Abuse,
+        "MISBEHAVIOR" => BehaviorType::Misbehavior,
+        _ => return Err(ErrorCode::InvalidBehaviorType.into()),
+    };
+    Ok(behavior_type as u8)
+}
+
+fn behavior_type_label(behavior_type: u8) -> &'static str {
+    match behavior_type {
+        t if t == BehaviorType::Spam as u8 => "SPAM",
+        t if t == BehaviorType::Fraud as u8 => "FRAUD",
+        t if t == BehaviorType::Attack as u8 => "ATTACK",
+        t if t == BehaviorType::Abuse as u8 => "ABUSE",
+        _ => "MISBEHAVIOR",
+    }
+}
+
+/// Record a moderator's vote on a pending report; each moderator votes once
+fn record_report_vote(
+    verification: &Verification,
+    report: &mut MaliciousReport,
+    moderator: &Pubkey,
+    confirm: bool,
+    now: i64,
+) -> Result<()> {
+    require!(is_moderator(verification, moderator), ErrorCode::NotModerator);
+    require!(*moderator != report.reporter, ErrorCode::ReporterCannotModerate);
+    require!(report.status == ReportStatus::Pending as u8, ErrorCode::ReportNotPending);
+    let expires_at = report.created_at
+        .checked_add(REPORT_TIMEOUT)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(now < expires_at, ErrorCode::ReportExpired);
+
+    // Votes from moderators removed since they voted no longer count
+    let mut votes: Vec<(Pubkey, bool)> = report.voters[..report.num_votes as usize]
+        .iter()
+        .zip(report.confirms.iter())
+        .filter(|(voter, _)| is_moderator(verification, voter))
+        .map(|(voter, confirm)| (*voter, *confirm))
+        .collect();
+    require!(votes.iter().all(|(voter, _)| voter != moderator), ErrorCode::AlreadyVoted);
+    votes.push((*moderator, confirm));
+    require!(votes.len() <= MAX_MODERATORS, ErrorCode::MaxModeratorsReached);
+
+    report.voters = [Pubkey::default(); MAX_MODERATORS];
+    report.confirms = [false; MAX_MODERATORS];
+    for (i, (voter, confirm)) in votes.iter().enumerate() {
+        report.voters[i] = *voter;
+        report.confirms[i] = *confirm;
+    }
+    report.num_votes = votes.len() as u8;
+    report.confirmations = votes.iter().filter(|(_, confirm)| *confirm).count() as u8;
+    report.dismissals = report.num_votes - report.confirmations;
+
+    Ok(())
+}
+
+/// Record a moderator's vote on a pending appeal; each moderator votes once
+fn record_appeal_vote(
+    verification: &Verification,
+    appeal: &mut BlacklistAppeal,
+    moderator: &Pubkey,
+) -> Result<()> {
+    require!(is_moderator(verification, moderator), ErrorCode::NotModerator);
+
+    let votes = appeal.num_votes as usize;
+    require!(votes < MAX_MODERATORS, ErrorCode::MaxModeratorsReached);
+    require!(!appeal.voters[..votes].contains(moderator), ErrorCode::AlreadyVoted);
+    appeal.voters[votes] = *moderator;
+    appeal.num_votes = appeal.num_votes.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(())
+}
+
+/// Blacklist an agent for `duration` seconds (0 = permanent). An active
+/// blacklisting is only ever extended: the higher severity and the later
+/// expiry win.
+fn apply_blacklist(agent: &mut AgentRecord, severity: u8, now: i64, duration: i64) -> Result<()> {
+    let expires_at = if duration == 0 {
+        0
+    } else {
+        now.checked_add(duration).ok_or(ErrorCode::MathOverflow)?
+    };
+
+    if agent.is_blacklisted_at(now) {
+        agent.severity = agent.severity.max(severity);
+        if agent.blacklist_expires_at != 0 && (expires_at == 0 || expires_at > agent.blacklist_expires_at) {
+            agent.blacklist_expires_at = expires_at;
+        }
+    } else {
+        agent.is_blacklisted = true;
+        agent.severity = severity;
+        agent.blacklisted_at = now;
+        agent.blacklist_expires_at = expires_at;
+        agent.appeal_status = AppealStatus::None as u8;
+    }
+
+    Ok(())
+}
+
+/// Drop a resolved report from the agent's pending count. Reports filed before
+/// the count was tracked were never added to it, hence the saturating subtract.
+fn release_pending_report(agent: &mut AgentRecord) {
+    agent.pending_reports = agent.pending_reports.saturating_sub(1);
+}
+
+/// Forgive one failed verification attempt per elapsed `cooldown`
+fn decay_failed_attempts(agent: &mut AgentRecord, now: i64, cooldown: i64) {
+    if agent.failed_attempts == 0 || cooldown <= 0 {
+        return;
+    }
+    let periods = now.saturating_sub(agent.last_failed_attempt) / cooldown;
+    if periods <= 0 {
+        return;
+    }
+    let forgiven = u8::try_from(periods).unwrap_or(u8::MAX);
+    agent.failed_attempts = agent.failed_attempts.saturating_sub(forgiven);
+    agent.last_failed_attempt = agent.last_failed_attempt.saturating_add(periods.saturating_mul(cooldown));
+}
+
+/// Move a bond out of a program-owned account, leaving its rent in place
+fn pay_out_bond(from: &AccountInfo, to: &AccountInfo, bond: u64) -> Result<()> {
+    if bond == 0 {
+        return Ok(());
+    }
+    **from.try_borrow_mut_lamports()? = from
+        .lamports()
+        .checked_sub(bond)
+        .ok_or(ErrorCode::MathOverflow)?;
+    **to.try_borrow_mut_lamports()? = to
+        .lamports()
+        .checked_add(bond)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(())
+}
+
+/// Mark a session verified and write the agent's `IdentityProof`
+fn complete_verification(
+    verification: &mut Verification,
+    session: &mut VerificationSession,
+    agent: &mut AgentRecord,
+    identity_proof: &mut IdentityProof,
+    identity_proof_bump: u8,
+    timestamp: i64,
+) -> Result<()> {
+    session.status = VerificationStatus::Verified as u8;
+    session.is_valid = true;
+
+    verification.total_successful_verifications = verification.total_successful_verifications.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+    // Store identity proof
+    identity_proof.agent = session.agent;
+    identity_proof.did_document = session.did_document.clone();
+    identity_proof.public_key = session.public_key.clone();
+    identity_proof.commitment = session.commitment;
+    identity_proof.nullifier = session.nullifier;
+    identity_proof.proof = session.proof.clone();
+    identity_proof.timestamp = timestamp;
+    identity_proof.is_verified = true;
+    identity_proof.bump = identity_proof_bump;
+
+    // Reset failed attempts
+    agent.failed_attempts = 0;
+
+    emit!(VerificationCompletedEvent {
+        session_id: session.session_id,
+        agent: session.agent,
+        is_valid: true,
+    });
+
+    emit!(IdentityVerifiedEvent {
+        agent: session.agent,
+        did_document: session.did_document.clone(),
+        timestamp,
+    });
+
+    Ok(())
+}
+
+/// Public inputs of the identity circuit: commitment, nullifier, agent and
+/// the DID document / public key the session was opened with
+pub fn identity_public_inputs(session: &VerificationSession) -> [[u8; 32]; 4] {
+    let did_hash = hashv(&[
+        hashv(&[session.did_document.as_bytes()]).as_ref(),
+        hashv(&[session.public_key.as_bytes()]).as_ref(),
+    ]);
+    [
+        session.commitment,
+        session.nullifier,
+        hash_to_field(hashv(&[session.agent.as_ref()]).to_bytes()),
+        hash_to_field(did_hash.to_bytes()),
+    ]
+}
+
+/// Truncate a hash to 248 bits so it is always below the scalar field modulus
+fn hash_to_field(mut hash: [u8; 32]) -> [u8; 32] {
+    hash[0] = 0;
+    hash
+}
+
+/// Check a Groth16 proof against the identity verifying key.
+///
+/// A malformed or non-verifying proof counts as a failed attempt rather than an
+/// error; only a verifying key that does not match the circuit is an error.
+fn verify_groth16_proof(
+    verifying_key: &VerifyingKey,
+    proof: &Groth16Proof,
+    public_inputs: &[[u8; 32]],
+) -> Result<bool> {
+    let points = Groth16VerifyingKey {
+        alpha_g1: &verifying_key.alpha_g1,
+        beta_g2: &verifying_key.beta_g2,
+        gamma_g2: &verifying_key.gamma_g2,
+        delta_g2: &verifying_key.delta_g2,
+        ic: &verifying_key.ic,
+    };
+    let proof = Groth16ProofPoints {
+        a: &proof.a,
+        b: &proof.b,
+        c: &proof.c,
+    };
+
+    match diap_crypto::verify_groth16_proof(&points, &proof, public_inputs) {
+        Ok(()) => Ok(true),
+        Err(CryptoError::InvalidVerifyingKey) => Err(ErrorCode::InvalidVerifyingKey.into()),
+        Err(_) => Ok(false),
+    }
 }
 
 fn verify_reputation_proof(proof: [u8; 8], agent: Pubkey, reputation: u64) -> bool {
@@ -772,3 +3209,22 @@ fn verify_reputation_proof(proof: [u8; 8], agent: Pubkey, reputation: u64) -> bo
         // Agent pubkey should be in a valid range (not the system program or other special addresses)
         && agent != Pubkey::default()
 }
+
+/// Require the instruction preceding this one to be an Ed25519 sigverify
+/// instruction carrying a signature over `message` by `signer`, and return it.
+fn find_ed25519_signature(
+    instructions: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<[u8; 64]> {
+    let signatures = load_ed25519_signatures(instructions).map_err(|error| match error {
+        CryptoError::MissingEd25519Instruction => error!(ErrorCode::MissingEd25519Instruction),
+        _ => error!(ErrorCode::InvalidEd25519Instruction),
+    })?;
+
+    signatures
+        .iter()
+        .find(|entry| entry.public_key == *signer && entry.message == message)
+        .map(|entry| entry.signature)
+        .ok_or(ErrorCode::MissingIssuerSignature.into())
+}